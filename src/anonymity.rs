@@ -0,0 +1,185 @@
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+use regex::Regex;
+use reqwest::Url;
+use crate::random_user_agent;
+use crate::utility::BoxError;
+
+/// How much a proxy reveals about the client behind it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AnonymityLevel {
+    /// Our real address reaches the target.
+    Transparent,
+    /// Our address is hidden but the proxy announces itself through headers such as `Via`.
+    Anonymous,
+    /// The request is indistinguishable from a direct one.
+    Elite,
+}
+
+impl std::fmt::Display for AnonymityLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnonymityLevel::Transparent => write!(f, "transparent"),
+            AnonymityLevel::Anonymous => write!(f, "anonymous"),
+            AnonymityLevel::Elite => write!(f, "elite"),
+        }
+    }
+}
+
+impl FromStr for AnonymityLevel {
+    type Err = BoxError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "transparent" => Ok(AnonymityLevel::Transparent),
+            "anonymous" => Ok(AnonymityLevel::Anonymous),
+            "elite" => Ok(AnonymityLevel::Elite),
+            _ => Err(format!("unknown anonymity level: {}", s).into()),
+        }
+    }
+}
+
+fn parse_ip(token: &str) -> Option<IpAddr> {
+    return IpAddr::from_str(token).ok()
+        .or_else(|| SocketAddr::from_str(token).ok().map(|addr| addr.ip()));
+}
+
+/// Addresses anywhere in `text`, e.g. in `X-Forwarded-For: 203.0.113.7:4711, 10.0.0.1` or
+/// `Forwarded: for="[2001:db8::1]"`. IPv4-mapped IPv6 addresses are returned as IPv4.
+fn echoed_ips(text: &str) -> Vec<IpAddr> {
+    return text.split(|c: char| !(c.is_ascii_hexdigit() || c == '.' || c == ':'))
+        .filter_map(|token| {
+            // Hex letters of a header name can run into its value, as in `for:203.0.113.7`.
+            return parse_ip(token).or_else(|| token.split_once(':').and_then(|(_, value)| parse_ip(value)));
+        })
+        .map(|ip| ip.to_canonical())
+        .collect();
+}
+
+/// Classifies a judge's echo of the request we sent through a proxy. `real_ip` only counts as
+/// leaked when the echo contains that whole address, not another one it is a substring of.
+pub fn classify(echo: &str, real_ip: &str) -> AnonymityLevel {
+    lazy_static::lazy_static! {
+        static ref PROXY_HEADER_PATTERN: Regex = Regex::new(obfstr::obfstr!(
+            r#"(?im)(?:^|[\s"'{,])(?:via|forwarded|x-forwarded-for|x-forwarded-host|x-real-ip|x-proxy-id|proxy-connection|client-ip)["']?\s*[:=]"#
+        )).expect(obfstr::obfstr!("proxy_header_pattern construction"));
+    }
+
+    if let Ok(real_ip) = IpAddr::from_str(real_ip.trim()) {
+        if echoed_ips(echo).contains(&real_ip.to_canonical()) {
+            return AnonymityLevel::Transparent;
+        }
+    }
+
+    if PROXY_HEADER_PATTERN.is_match(echo) {
+        return AnonymityLevel::Anonymous;
+    }
+
+    return AnonymityLevel::Elite;
+}
+
+/// A header-echoing endpoint that proxied requests are sent to for anonymity classification.
+/// The judge should be plain HTTP, otherwise HTTP proxies only see a `CONNECT` and cannot add headers.
+#[derive(Debug, Clone)]
+pub struct ProxyJudge {
+    pub url: Url,
+    pub timeout: Duration,
+}
+
+impl Default for ProxyJudge {
+    fn default() -> Self {
+        return ProxyJudge::new(Url::from_str(obfstr::obfstr!("http://httpbin.org/headers"))
+            .expect(obfstr::obfstr!("default judge url")));
+    }
+}
+
+impl ProxyJudge {
+    pub fn new(url: Url) -> ProxyJudge {
+        return ProxyJudge {
+            url,
+            timeout: Duration::from_secs(30)
+        }
+    }
+
+    pub async fn judge(&self, client: &reqwest::Client, real_ip: &str) -> Result<AnonymityLevel, BoxError> {
+        let echo = client.get(self.url.clone())
+            .header(obfstr::obfstr!("User-Agent"), random_user_agent())
+            .header(obfstr::obfstr!("Accept-Language"), obfstr::obfstr!("en-US,en;q=0.9"))
+            .timeout(self.timeout)
+            .send().await?
+            .text().await?;
+
+        let level = classify(&echo, real_ip);
+
+        #[cfg(feature = "logging")]
+        log::debug!("judge {} classified {:?}", self.url, level);
+        return Ok(level);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    // Echoes the request head back as the response body.
+    async fn spawn_echo_server() -> Result<Url, BoxError> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = Url::from_str(&format!("http://{}/headers", listener.local_addr()?))?;
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+
+                let head = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", request.len());
+                let _ = stream.write_all(head.as_bytes()).await;
+                let _ = stream.write_all(&request).await;
+            }
+        });
+
+        return Ok(url);
+    }
+
+    #[test]
+    fn test_classify_compares_whole_addresses() {
+        assert_eq!(classify(r#"{"origin": "11.2.3.45"}"#, "1.2.3.4"), AnonymityLevel::Elite);
+        assert_eq!(classify("X-Forwarded-For: 11.2.3.45", "1.2.3.4"), AnonymityLevel::Anonymous);
+        assert_eq!(classify(r#"{"origin": "1.2.3.4"}"#, "1.2.3.4"), AnonymityLevel::Transparent);
+        assert_eq!(classify("x-forwarded-for:1.2.3.4:4711, 10.0.0.1", "1.2.3.4"), AnonymityLevel::Transparent);
+        assert_eq!(classify(r#"Forwarded: for="[2001:db8::1]:4711""#, "2001:db8::1"), AnonymityLevel::Transparent);
+        assert_eq!(classify("X-Real-IP: ::ffff:1.2.3.4", "1.2.3.4"), AnonymityLevel::Transparent);
+    }
+
+    #[tokio::test]
+    async fn test_judge_levels() -> Result<(), BoxError> {
+        let judge = ProxyJudge::new(spawn_echo_server().await?);
+        let real_ip = "203.0.113.7";
+
+        // Stand-ins for what a transparent or anonymous proxy would add to our request.
+        let transparent = reqwest::Client::builder()
+            .default_headers(std::iter::once((
+                reqwest::header::HeaderName::from_static("x-forwarded-for"),
+                reqwest::header::HeaderValue::from_static("203.0.113.7")
+            )).collect())
+            .build()?;
+        let anonymous = reqwest::Client::builder()
+            .default_headers(std::iter::once((
+                reqwest::header::VIA,
+                reqwest::header::HeaderValue::from_static("1.1 squid")
+            )).collect())
+            .build()?;
+
+        assert_eq!(judge.judge(&transparent, real_ip).await?, AnonymityLevel::Transparent);
+        assert_eq!(judge.judge(&anonymous, real_ip).await?, AnonymityLevel::Anonymous);
+        assert_eq!(judge.judge(&reqwest::Client::new(), real_ip).await?, AnonymityLevel::Elite);
+        return Ok(());
+    }
+}
@@ -0,0 +1,113 @@
+use reqwest::Url;
+use std::str::FromStr;
+use std::time::Duration;
+use base64::Engine;
+use regex::Regex;
+use std::error::Error;
+use crate::crawler::{get_text, html_attribute, SearchEngine, SearchResult};
+
+/// Results shown per page, which the `first` parameter counts in.
+const PAGE_SIZE: u32 = 10;
+
+/// Bing wraps result links in `/ck/a` click trackers carrying the target as `u=a1<base64url>`.
+fn unwrap_tracker(url: Url) -> Option<Url> {
+    if !url.domain().is_some_and(|domain| domain.ends_with(obfstr::obfstr!("bing.com"))) {
+        return Some(url);
+    }
+
+    let target = url.query_pairs().find(|(name, _)| name == "u")?.1;
+    let encoded = target.strip_prefix("a1")?.trim_end_matches('=');
+    let decoded = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(encoded).ok()?;
+    return Url::from_str(&String::from_utf8(decoded).ok()?).ok();
+}
+
+fn parse_result_urls(text: &str) -> Vec<Url> {
+    lazy_static::lazy_static! {
+        static ref BING_RESULT_PATTERN: Regex = Regex::new(obfstr::obfstr!(r#"(?is)<li class="b_algo"[^>]*>.*?<h2[^>]*>\s*(<a\b[^>]*>)"#))
+            .expect(obfstr::obfstr!("bing_result_pattern construction"));
+    }
+
+    let mut urls: Vec<Url> = Vec::new();
+    for captures in BING_RESULT_PATTERN.captures_iter(text) {
+        let url = html_attribute(&captures[1], "href")
+            .and_then(|href| Url::from_str(&href).ok())
+            .and_then(unwrap_tracker);
+
+        if let Some(url) = url {
+            if url.domain().is_some() && !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+    }
+
+    return urls;
+}
+
+fn has_next_page(text: &str) -> bool {
+    return text.contains(obfstr::obfstr!("sb_pagN"));
+}
+
+/// Bing's HTML results.
+pub struct Bing {
+    web: reqwest::Client,
+    pub timeout: Duration
+}
+
+impl Bing {
+    pub fn new(web: reqwest::Client) -> Bing {
+        return Bing {
+            web,
+            timeout: Duration::from_secs(30)
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Bing {
+        self.timeout = timeout;
+        return self;
+    }
+}
+
+#[async_trait::async_trait]
+impl SearchEngine for Bing {
+    async fn search_n(&self, text: &str, page: u16) -> Result<SearchResult, Box<dyn Error + Send + Sync>> {
+        let first = (u32::from(page) * PAGE_SIZE + 1).to_string();
+        let url = Url::parse_with_params(obfstr::obfstr!("https://www.bing.com/search"), &[("q", text), ("first", &first)])?;
+        let text = get_text(&self.web, url, self.timeout).await?;
+
+        return Ok(SearchResult {
+            urls: parse_result_urls(&text),
+            page,
+            has_next: has_next_page(&text)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_result_urls() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let html = r#"
+<ol id="b_results">
+  <li class="b_algo" data-id="">
+    <div class="b_tpcn"><a class="tilk" href="https://www.free-proxy.example/" h="ID=SERP,5071.1"><div class="tpic"></div></a></div>
+    <h2><a href="https://www.free-proxy.example/list?country=all&amp;page=1" h="ID=SERP,5072.1">Free Proxy List - Updated Hourly</a></h2>
+    <div class="b_caption"><p>Free proxies checked every 10 minutes.</p></div>
+  </li>
+  <li class="b_algo" data-id="">
+    <h2 class=""><a target="_blank" href="https://www.bing.com/ck/a?!&amp;&amp;p=123&amp;u=a1aHR0cHM6Ly9saXN0cy5leGFtcGxlLm9yZy9zb2NrczUudHh0&amp;ntb=1">SOCKS5 list</a></h2>
+  </li>
+  <li class="b_ans"><h2><a href="https://www.bing.com/videos">Videos</a></h2></li>
+</ol>
+<a class="sb_pagN sb_pagN_bp b_widePag sb_bp" title="Next page" href="/search?q=free+proxy+list&amp;first=11">Next</a>"#;
+
+        assert_eq!(parse_result_urls(html), [
+            Url::from_str("https://www.free-proxy.example/list?country=all&page=1")?,
+            Url::from_str("https://lists.example.org/socks5.txt")?
+        ]);
+        assert!(has_next_page(html));
+        assert!(!has_next_page(&html[..html.find("<a class=\"sb_pagN").unwrap()]));
+        return Ok(());
+    }
+}
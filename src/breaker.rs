@@ -0,0 +1,121 @@
+use std::time::{Duration, SystemTime};
+
+/// When a proxy is benched and for how long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BreakerPolicy {
+    /// Failures in a row reported by callers that open the breaker.
+    pub failure_threshold: u32,
+    /// Time an open breaker keeps the proxy out of selection before it is probed.
+    pub cooldown: Duration,
+}
+
+impl Default for BreakerPolicy {
+    fn default() -> Self {
+        return BreakerPolicy {
+            failure_threshold: 3,
+            cooldown: Duration::from_secs(60)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    /// The proxy is selectable.
+    Closed,
+    /// The proxy is benched until the given time.
+    Open { until: SystemTime },
+    /// The cooling-off period is over and a probe decides whether the proxy is re-admitted.
+    HalfOpen,
+}
+
+/// Per-endpoint circuit breaker fed by the outcomes callers report and closed again by fresh
+/// successful tests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CircuitBreaker {
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        return CircuitBreaker::new();
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new() -> CircuitBreaker {
+        return CircuitBreaker {
+            state: BreakerState::Closed,
+            consecutive_failures: 0
+        }
+    }
+
+    /// Whether selection may hand out the proxy.
+    pub fn is_closed(&self) -> bool {
+        return self.state == BreakerState::Closed;
+    }
+
+    pub fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+        self.state = BreakerState::Closed;
+    }
+
+    /// Counts a failure, opening the breaker at the policy's threshold. A failed probe
+    /// reopens it straight away.
+    pub fn record_failure(&mut self, now: SystemTime, policy: &BreakerPolicy) {
+        self.consecutive_failures += 1;
+        let trips = match self.state {
+            BreakerState::Closed => self.consecutive_failures >= policy.failure_threshold,
+            BreakerState::HalfOpen => true,
+            BreakerState::Open { .. } => false,
+        };
+
+        if trips {
+            self.state = BreakerState::Open { until: now + policy.cooldown };
+        }
+    }
+
+    /// Moves an open breaker whose cooldown has passed to half-open, returning whether the
+    /// proxy should be probed now.
+    pub fn take_probe(&mut self, now: SystemTime) -> bool {
+        return match self.state {
+            BreakerState::Open { until } if until <= now => {
+                self.state = BreakerState::HalfOpen;
+                true
+            },
+            _ => false,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_breaker_transitions() {
+        let policy = BreakerPolicy { failure_threshold: 2, cooldown: Duration::from_secs(60) };
+        let mut breaker = CircuitBreaker::new();
+
+        breaker.record_failure(UNIX_EPOCH, &policy);
+        assert!(breaker.is_closed());
+        breaker.record_failure(UNIX_EPOCH, &policy);
+        assert_eq!(breaker.state, BreakerState::Open { until: UNIX_EPOCH + policy.cooldown });
+
+        assert!(!breaker.take_probe(UNIX_EPOCH + Duration::from_secs(30)));
+        assert!(breaker.take_probe(UNIX_EPOCH + Duration::from_secs(60)));
+        assert_eq!(breaker.state, BreakerState::HalfOpen);
+        assert!(!breaker.is_closed());
+
+        // A failed probe benches the proxy for another full cooldown.
+        let later = UNIX_EPOCH + Duration::from_secs(60);
+        breaker.record_failure(later, &policy);
+        assert_eq!(breaker.state, BreakerState::Open { until: later + policy.cooldown });
+
+        assert!(breaker.take_probe(later + policy.cooldown));
+        breaker.record_success();
+        assert!(breaker.is_closed());
+        assert_eq!(breaker.consecutive_failures, 0);
+    }
+}
@@ -0,0 +1,286 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use rand::Rng;
+use rand::distributions::Alphanumeric;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use crate::endpoint::ProxyEndpoint;
+use crate::proxy::SupportedProtocols;
+use crate::tunnel::{self, TargetAddr};
+use crate::utility::BoxError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainHop {
+    pub proxy: ProxyEndpoint,
+    pub protocol: SupportedProtocols,
+}
+
+/// A sequence of proxies traffic passes through in order, each one reached through a tunnel
+/// opened by the one before it. An empty chain connects directly.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ProxyChain {
+    pub hops: Vec<ChainHop>,
+}
+
+impl ProxyChain {
+    pub fn new() -> ProxyChain {
+        return ProxyChain::default();
+    }
+
+    /// Appends a hop after the existing ones.
+    pub fn hop(mut self, proxy: ProxyEndpoint, protocol: SupportedProtocols) -> ProxyChain {
+        self.hops.push(ChainHop { proxy, protocol });
+        return self;
+    }
+
+    pub fn len(&self) -> usize {
+        return self.hops.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.hops.is_empty();
+    }
+
+    /// Opens nested tunnels through every hop to `target`.
+    pub async fn connect(&self, target: &TargetAddr) -> Result<TcpStream, BoxError> {
+        let first = match self.hops.first() {
+            Some(first) => first,
+            None => return Ok(TcpStream::connect(target.to_string()).await?),
+        };
+
+        let mut stream = TcpStream::connect(first.proxy.authority()).await?;
+        for (index, hop) in self.hops.iter().enumerate() {
+            let next = match self.hops.get(index + 1) {
+                Some(next) => TargetAddr::new(&next.proxy.host.to_string(), next.proxy.port),
+                None => target.clone(),
+            };
+
+            tunnel::handshake(&mut stream, &hop.proxy, &hop.protocol, &next).await?;
+        }
+
+        #[cfg(feature = "logging")]
+        log::debug!("chained {} hops to {}", self.hops.len(), target);
+        return Ok(stream);
+    }
+
+    /// Serves the chain as a local SOCKS5 proxy, for clients such as reqwest that only take a
+    /// single proxy URL. Every connection has to log in with the random credentials of the
+    /// forwarder or of one of its routes, so other local processes cannot use the chain. Route
+    /// connections go through the route's hop after the chain.
+    pub async fn spawn_forwarder(&self) -> Result<ChainForwarder, BoxError> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let chain = Arc::new(self.clone());
+        let login = RouteLogin::new();
+        let routes = Arc::new(Mutex::new(HashMap::new()));
+        lock_routes(&routes).insert(login.username.clone(), (login.password.clone(), None));
+
+        let served_routes = routes.clone();
+        let task = tokio::spawn(async move {
+            while let Ok((mut client, _)) = listener.accept().await {
+                let chain = chain.clone();
+                let routes = served_routes.clone();
+                tokio::spawn(async move {
+                    let authenticate = |username: &str, password: &str| -> bool {
+                        return lock_routes(&routes).get(username).is_some_and(|(expected, _)| expected == password);
+                    };
+                    let (target, username) = tunnel::accept_socks5(&mut client, Some(&authenticate)).await?;
+
+                    let route = username.and_then(|username| lock_routes(&routes).get(&username).cloned());
+                    let chain = match route {
+                        Some((_, None)) => chain.as_ref().clone(),
+                        Some((_, Some(hop))) => chain.as_ref().clone().hop(hop.proxy, hop.protocol),
+                        None => {
+                            client.write_all(&tunnel::socks5_reply(0x02)).await?;
+                            return Err(obfstr::obfstr!("forwarder route dropped during the handshake").into());
+                        }
+                    };
+
+                    let mut upstream = match chain.connect(&target).await {
+                        Ok(upstream) => upstream,
+                        Err(e) => {
+                            client.write_all(&tunnel::socks5_reply(0x04)).await?;
+                            return Err(e);
+                        }
+                    };
+
+                    client.write_all(&tunnel::socks5_reply(0x00)).await?;
+                    tokio::io::copy_bidirectional(&mut client, &mut upstream).await?;
+                    return Ok::<(), BoxError>(());
+                });
+            }
+        });
+
+        return Ok(ChainForwarder { addr, login, routes, task });
+    }
+}
+
+/// Password and, for routes, the extra hop, per forwarder username.
+type RouteTable = Arc<Mutex<HashMap<String, (String, Option<ChainHop>)>>>;
+
+fn lock_routes(routes: &RouteTable) -> std::sync::MutexGuard<'_, HashMap<String, (String, Option<ChainHop>)>> {
+    return routes.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+}
+
+/// Random credentials for a forwarder connection.
+#[derive(Debug, Clone)]
+struct RouteLogin {
+    username: String,
+    password: String,
+}
+
+impl RouteLogin {
+    fn new() -> RouteLogin {
+        let token = || rand::thread_rng().sample_iter(&Alphanumeric).take(24).map(char::from).collect::<String>();
+        return RouteLogin { username: token(), password: token() };
+    }
+
+    fn to_reqwest_proxy(&self, addr: SocketAddr) -> Result<reqwest::Proxy, BoxError> {
+        return Ok(reqwest::Proxy::all(format!("socks5h://{}:{}@{}", self.username, self.password, addr))?);
+    }
+}
+
+/// A `reqwest::Proxy` for `proxy`, reached through `forwarder`'s chain when there is one. The
+/// returned route has to be kept alive until the client has connected.
+pub fn reqwest_proxy_via(forwarder: Option<&ChainForwarder>, proxy: &ProxyEndpoint, protocol: &SupportedProtocols) -> Result<(reqwest::Proxy, Option<ForwarderRoute>), BoxError> {
+    return match forwarder {
+        Some(forwarder) => {
+            let route = forwarder.route(proxy, protocol)?;
+            Ok((route.to_reqwest_proxy()?, Some(route)))
+        },
+        None => Ok((proxy.to_reqwest_proxy(protocol)?, None)),
+    };
+}
+
+/// Local SOCKS5 listener in front of a `ProxyChain`, shared by every proxy reached through
+/// the chain. Dropping it stops accepting connections; connections already accepted keep running.
+#[derive(Debug)]
+pub struct ChainForwarder {
+    addr: SocketAddr,
+    /// Credentials for connections that end at the chain.
+    login: RouteLogin,
+    routes: RouteTable,
+    task: JoinHandle<()>,
+}
+
+impl ChainForwarder {
+    pub fn local_addr(&self) -> SocketAddr {
+        return self.addr;
+    }
+
+    /// The forwarder as a `socks5h` proxy, so that target names are resolved at the last hop.
+    pub fn to_reqwest_proxy(&self) -> Result<reqwest::Proxy, BoxError> {
+        return self.login.to_reqwest_proxy(self.addr);
+    }
+
+    /// Has connections logging in with the returned route's credentials continue from the chain
+    /// through `proxy`, until the route is dropped. HTTPS proxies cannot be a hop.
+    pub fn route(&self, proxy: &ProxyEndpoint, protocol: &SupportedProtocols) -> Result<ForwarderRoute, BoxError> {
+        if *protocol == SupportedProtocols::Https {
+            return Err(obfstr::obfstr!("tunnels through https proxies are not supported").into());
+        }
+
+        let login = RouteLogin::new();
+        let hop = ChainHop { proxy: proxy.clone(), protocol: protocol.clone() };
+        lock_routes(&self.routes).insert(login.username.clone(), (login.password.clone(), Some(hop)));
+        return Ok(ForwarderRoute { addr: self.addr, login, routes: self.routes.clone() });
+    }
+}
+
+/// A hop served by a `ChainForwarder`, removed again when dropped.
+#[derive(Debug)]
+pub struct ForwarderRoute {
+    addr: SocketAddr,
+    login: RouteLogin,
+    routes: RouteTable,
+}
+
+impl ForwarderRoute {
+    /// The route as a `socks5h` proxy logging in with the route's credentials.
+    pub fn to_reqwest_proxy(&self) -> Result<reqwest::Proxy, BoxError> {
+        return self.login.to_reqwest_proxy(self.addr);
+    }
+}
+
+impl Drop for ForwarderRoute {
+    fn drop(&mut self) {
+        lock_routes(&self.routes).remove(&self.login.username);
+    }
+}
+
+impl Drop for ChainForwarder {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use reqwest::Url;
+    use crate::tester::ProxyTester;
+    use crate::validator::ResponseValidator;
+
+    #[tokio::test]
+    async fn test_chain_nests_tunnels() -> Result<(), BoxError> {
+        let target = tunnel::spawn_http_server("hello").await?;
+        let (first, first_targets) = tunnel::spawn_connect_proxy().await?;
+        let (second, second_targets) = tunnel::spawn_connect_proxy().await?;
+
+        let chain = ProxyChain::new()
+            .hop(first.clone(), SupportedProtocols::Http)
+            .hop(second.clone(), SupportedProtocols::Http);
+        let forwarder = chain.spawn_forwarder().await?;
+        let client = reqwest::Client::builder().proxy(forwarder.to_reqwest_proxy()?).build()?;
+        let body = client.get(format!("http://{}/", target)).send().await?.text().await?;
+
+        assert_eq!(body, "hello");
+        assert_eq!(*first_targets.lock().unwrap(), [second.authority()]);
+        assert_eq!(*second_targets.lock().unwrap(), [target.to_string()]);
+
+        // Testing `second` through `first` never connects to it directly.
+        let tester = ProxyTester::new()
+            .without_judge()
+            .with_validators(vec![Arc::new(ResponseValidator::new(Url::from_str(&format!("http://{}/", target))?))])
+            .with_upstream(ProxyChain::new().hop(first, SupportedProtocols::Http));
+        tester.test_proxy(&SupportedProtocols::Http, &second).await?;
+        assert_eq!(*first_targets.lock().unwrap(), [second.authority(), second.authority()]);
+        return Ok(());
+    }
+
+    #[tokio::test]
+    async fn test_forwarder_routes_share_one_listener() -> Result<(), BoxError> {
+        let target = tunnel::spawn_http_server("hello").await?;
+        let (first, first_targets) = tunnel::spawn_connect_proxy().await?;
+        let (second, _) = tunnel::spawn_connect_proxy().await?;
+        let (third, _) = tunnel::spawn_connect_proxy().await?;
+
+        let forwarder = ProxyChain::new().hop(first, SupportedProtocols::Http).spawn_forwarder().await?;
+        let url = format!("http://{}/", target);
+        for hop in [&second, &third] {
+            let (proxy, route) = reqwest_proxy_via(Some(&forwarder), hop, &SupportedProtocols::Http)?;
+            let client = reqwest::Client::builder().proxy(proxy).build()?;
+            assert_eq!(client.get(&url).send().await?.text().await?, "hello");
+
+            // A dropped route no longer leads anywhere.
+            drop(route);
+            assert!(client.get(&url).send().await.is_err());
+        }
+
+        assert_eq!(*first_targets.lock().unwrap(), [second.authority(), third.authority()]);
+        assert!(forwarder.route(&second, &SupportedProtocols::Https).is_err());
+
+        // Connections without the credentials of a live route get nowhere.
+        let route = forwarder.route(&second, &SupportedProtocols::Http)?;
+        let addr = forwarder.local_addr();
+        for proxy in [format!("socks5h://{}", addr), format!("socks5h://{}:guess@{}", route.login.username, addr)] {
+            let client = reqwest::Client::builder().proxy(reqwest::Proxy::all(proxy)?).build()?;
+            assert!(client.get(&url).send().await.is_err());
+        }
+        assert_eq!(first_targets.lock().unwrap().len(), 2);
+        return Ok(());
+    }
+}
@@ -0,0 +1,249 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use reqwest::{IntoUrl, Method, RequestBuilder, Response, StatusCode};
+use tokio::sync::{Mutex, OnceCell};
+use crate::chain::{self, ChainForwarder, ProxyChain};
+use crate::outcome::FailureKind;
+use crate::proxy::{ProxyManager, ProxyTest, SupportedProtocols};
+use crate::random_user_agent;
+use crate::select::{LowestRtt, ProxyFilter, SelectionStrategy};
+use crate::utility::BoxError;
+
+/// HTTP client that sends each request through a proxy from the pool, retrying through a
+/// different proxy when one fails, and reports every attempt back to the pool.
+pub struct ProxiedClient {
+    pool: Arc<Mutex<ProxyManager>>,
+    strategy: std::sync::Mutex<Box<dyn SelectionStrategy>>,
+    pub filter: ProxyFilter,
+    /// Proxies tried per request, at least one.
+    pub max_attempts: usize,
+    /// Responses that count as a proxy failure and are retried, e.g. blocks and rate limits.
+    pub retry_statuses: Vec<StatusCode>,
+    pub timeout: Duration,
+    /// Proxies every request goes through before the selected one.
+    pub upstream: Option<ProxyChain>,
+    /// Serves `upstream` to every request, spawned by the first one.
+    forwarder: OnceCell<ChainForwarder>,
+}
+
+impl ProxiedClient {
+    pub fn new(pool: Arc<Mutex<ProxyManager>>) -> ProxiedClient {
+        return ProxiedClient {
+            pool,
+            strategy: std::sync::Mutex::new(Box::new(LowestRtt)),
+            filter: ProxyFilter::new(),
+            max_attempts: 3,
+            retry_statuses: vec![
+                StatusCode::FORBIDDEN,
+                StatusCode::PROXY_AUTHENTICATION_REQUIRED,
+                StatusCode::TOO_MANY_REQUESTS,
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT,
+            ],
+            timeout: Duration::from_secs(30),
+            upstream: None,
+            forwarder: OnceCell::new()
+        }
+    }
+
+    pub fn with_strategy(mut self, strategy: Box<dyn SelectionStrategy>) -> ProxiedClient {
+        self.strategy = std::sync::Mutex::new(strategy);
+        return self;
+    }
+
+    pub fn with_filter(mut self, filter: ProxyFilter) -> ProxiedClient {
+        self.filter = filter;
+        return self;
+    }
+
+    pub fn with_max_attempts(mut self, attempts: usize) -> ProxiedClient {
+        self.max_attempts = attempts;
+        return self;
+    }
+
+    pub fn with_retry_statuses(mut self, statuses: Vec<StatusCode>) -> ProxiedClient {
+        self.retry_statuses = statuses;
+        return self;
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> ProxiedClient {
+        self.timeout = timeout;
+        return self;
+    }
+
+    pub fn with_upstream(mut self, upstream: ProxyChain) -> ProxiedClient {
+        self.upstream = Some(upstream);
+        self.forwarder = OnceCell::new();
+        return self;
+    }
+
+    pub fn pool(&self) -> &Arc<Mutex<ProxyManager>> {
+        return &self.pool;
+    }
+
+    pub async fn get<U: IntoUrl>(&self, url: U) -> Result<Response, BoxError> {
+        return self.request(Method::GET, url, None).await;
+    }
+
+    pub async fn post<U: IntoUrl>(&self, url: U, body: Vec<u8>) -> Result<Response, BoxError> {
+        return self.request(Method::POST, url, Some(body)).await;
+    }
+
+    pub async fn request<U: IntoUrl>(&self, method: Method, url: U, body: Option<Vec<u8>>) -> Result<Response, BoxError> {
+        let url = url.into_url()?;
+        let key = url.host_str().map(|host| host.to_string());
+        return self.send(key.as_deref(), |client| {
+            let request = client.request(method.clone(), url.clone());
+            return match &body {
+                Some(body) => request.body(body.clone()),
+                None => request,
+            };
+        }).await;
+    }
+
+    /// Sends the request `build` makes with a proxied client, at most `max_attempts` times
+    /// through different proxies. `key` is passed on to the selection strategy. When every
+    /// attempt fails, the last retryable response or error is returned.
+    pub async fn send<F>(&self, key: Option<&str>, build: F) -> Result<Response, BoxError>
+        where F: Fn(&reqwest::Client) -> RequestBuilder {
+        let mut filter = self.filter.clone();
+        let mut last_error: Option<BoxError> = None;
+        for attempt in 1..=self.max_attempts.max(1) {
+            let test = match self.select(&filter, key).await {
+                Some(test) => test,
+                None => break,
+            };
+            filter = filter.exclude(test.proxy.clone());
+
+            let (proxy, _route) = chain::reqwest_proxy_via(self.forwarder().await?, &test.proxy, &test.protocol)?;
+            let client = reqwest::Client::builder()
+                .proxy(proxy)
+                .user_agent(random_user_agent())
+                .timeout(self.timeout)
+                .build()?;
+
+            let start = Instant::now();
+            match build(&client).send().await {
+                Ok(response) if self.retry_statuses.contains(&response.status()) => {
+                    let kind = match response.status() {
+                        StatusCode::PROXY_AUTHENTICATION_REQUIRED => FailureKind::AuthRequired,
+                        _ => FailureKind::BadStatus,
+                    };
+                    self.pool.lock().await.report_failure(&test.proxy, kind);
+
+                    #[cfg(feature = "logging")]
+                    log::debug!("attempt {} through {} answered {}", attempt, test.proxy, response.status());
+                    if attempt == self.max_attempts.max(1) {
+                        return Ok(response);
+                    }
+                    last_error = Some(format!("{} answered {}", test.proxy, response.status()).into());
+                },
+                Ok(response) => {
+                    self.pool.lock().await.report_success(&test.proxy, start.elapsed());
+                    return Ok(response);
+                },
+                Err(e) => {
+                    self.pool.lock().await.report_failure(&test.proxy, FailureKind::classify(&e));
+
+                    #[cfg(feature = "logging")]
+                    log::debug!("attempt {} through {} failed: {}", attempt, test.proxy, e);
+                    if !(e.is_connect() || e.is_timeout()) {
+                        return Err(e.into());
+                    }
+                    last_error = Some(e.into());
+                }
+            }
+        }
+
+        return Err(last_error.unwrap_or_else(|| obfstr::obfstr!("no proxy matches the filter").into()));
+    }
+
+    /// The forwarder in front of `upstream`, `None` without one.
+    async fn forwarder(&self) -> Result<Option<&ChainForwarder>, BoxError> {
+        let upstream = match &self.upstream {
+            Some(upstream) => upstream,
+            None => return Ok(None),
+        };
+
+        return Ok(Some(self.forwarder.get_or_try_init(|| upstream.spawn_forwarder()).await?));
+    }
+
+    async fn select(&self, filter: &ProxyFilter, key: Option<&str>) -> Option<ProxyTest> {
+        // Behind an upstream, HTTPS proxies would be a chain hop, which tunnels do not speak.
+        let filter = match self.upstream {
+            Some(_) => filter.clone().exclude_protocol(SupportedProtocols::Https),
+            None => filter.clone(),
+        };
+
+        let mut pool = self.pool.lock().await;
+        let mut strategy = self.strategy.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        return pool.select(strategy.as_mut(), &filter, key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use crate::endpoint::ProxyEndpoint;
+    use crate::proxy::SupportedProtocols;
+
+    // An HTTP proxy that answers every request itself with `status` and `body`.
+    async fn spawn_fake_proxy(status: &'static str, body: &'static str) -> Result<ProxyEndpoint, BoxError> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let proxy = ProxyEndpoint::from(listener.local_addr()?);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+
+                let response = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body);
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+
+        return Ok(proxy);
+    }
+
+    fn test_for(proxy: &ProxyEndpoint, rtt_ms: u64) -> ProxyTest {
+        return ProxyTest {
+            proxy: proxy.clone(),
+            protocol: SupportedProtocols::Http,
+            status: StatusCode::OK,
+            text: String::new(),
+            time: SystemTime::now(),
+            rtt: Duration::from_millis(rtt_ms),
+            anonymity: None
+        };
+    }
+
+    #[tokio::test]
+    async fn test_retries_through_another_proxy() -> Result<(), BoxError> {
+        let blocked = spawn_fake_proxy("403 Forbidden", "blocked").await?;
+        let working = spawn_fake_proxy("200 OK", "hello").await?;
+
+        let mut mgr = ProxyManager::new();
+        mgr.import_test(test_for(&blocked, 10));
+        mgr.import_test(test_for(&working, 500));
+        let pool = Arc::new(Mutex::new(mgr));
+
+        let client = ProxiedClient::new(pool.clone());
+        let response = client.get("http://example.com/").await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await?, "hello");
+
+        let pool = pool.lock().await;
+        assert_eq!(pool.health(&blocked).map(|health| health.consecutive_failures), Some(1));
+        assert_eq!(pool.health(&working).map(|health| health.history.len()), Some(2));
+        return Ok(());
+    }
+}
@@ -1,131 +1,385 @@
 use reqwest::{Url, StatusCode};
-use std::net::Ipv4Addr;
 use std::time::Duration;
 use std::str::FromStr;
 use regex::Regex;
+use crate::endpoint::{decode_credentials, is_valid_domain, suffix_credentials, ProxyCredentials, ProxyEndpoint, ProxyHost};
+use crate::proxy::SupportedProtocols;
 use crate::random_user_agent;
+use std::collections::HashSet;
 use std::error::Error;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use futures::future;
+use futures::stream::{self, BoxStream, StreamExt};
 
+/// Pages scraped at once by `Crawler::crawl_stream`.
+pub const CRAWL_CONCURRENCY: usize = 20;
+
+/// One page of search results.
 pub struct SearchResult {
     pub urls: Vec<Url>,
+    /// Zero-based index of the page.
+    pub page: u16,
+    /// Whether the engine offered another page after this one.
+    pub has_next: bool,
+}
+
+/// Result pages in order, ending after the last page, the page cap or the first error.
+pub type SearchResultPages<'a> = BoxStream<'a, Result<SearchResult, Box<dyn Error + Send + Sync>>>;
+
+/// Finds pages that may list proxies.
+#[async_trait::async_trait]
+pub trait SearchEngine: Send + Sync {
+    /// Fetches page `page` of the results for `text`, fetching the pages before it as well
+    /// when the engine needs their state to get there.
+    async fn search_n(&self, text: &str, page: u16) -> Result<SearchResult, Box<dyn Error + Send + Sync>>;
+
+    async fn search(&self, text: &str) -> Result<SearchResult, Box<dyn Error + Send + Sync>> {
+        return self.search_n(text, 0).await;
+    }
+
+    /// Streams up to `max_pages` result pages for `text` through `search_n`.
+    fn pages<'a>(&'a self, text: &'a str, max_pages: u16) -> SearchResultPages<'a> {
+        return stream::unfold((0, true), move |(page, more)| async move {
+            if !more || page >= max_pages {
+                return None;
+            }
+
+            return match self.search_n(text, page).await {
+                Ok(result) => {
+                    let more = result.has_next;
+                    Some((Ok(result), (page + 1, more)))
+                },
+                Err(e) => Some((Err(e), (page + 1, false))),
+            };
+        }).boxed();
+    }
+}
+
+/// Retrieves the text of a page.
+#[async_trait::async_trait]
+pub trait Fetcher: Send + Sync {
+    async fn fetch(&self, url: &Url) -> Result<String, Box<dyn Error + Send + Sync>>;
+}
+
+/// Parses proxies out of a fetched page.
+pub trait ProxyExtractor: Send + Sync {
+    fn extract(&self, url: &Url, text: &str) -> Result<Vec<ProxyEndpoint>, Box<dyn Error + Send + Sync>>;
+}
+
+/// Plain GET requests with a random user agent.
+pub struct HttpFetcher {
+    web: reqwest::Client,
+    pub timeout: Duration
 }
 
-impl SearchResult {
-    pub fn pages(&self) -> SearchResultPages {
-        return SearchResultPages {
-            index: 0
+impl HttpFetcher {
+    pub fn new(web: reqwest::Client) -> HttpFetcher {
+        return HttpFetcher {
+            web,
+            timeout: Duration::from_secs(30)
         }
     }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> HttpFetcher {
+        self.timeout = timeout;
+        return self;
+    }
 }
 
-pub struct SearchResultPages {
-    pub index: u16
+/// GETs `url` with browser-like headers, failing on error statuses. Shared by the fetcher and
+/// the search engines.
+pub(crate) async fn get_text(web: &reqwest::Client, url: Url, timeout: Duration) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let response = web.get(url)
+        .header(obfstr::obfstr!("User-Agent"), random_user_agent())
+        .header(obfstr::obfstr!("Accept-Language"), obfstr::obfstr!("en-US,en;q=0.9"))
+        .timeout(timeout)
+        .send().await?
+        .error_for_status()?;
+
+    return Ok(response.text().await?);
+}
+
+#[async_trait::async_trait]
+impl Fetcher for HttpFetcher {
+    async fn fetch(&self, url: &Url) -> Result<String, Box<dyn Error + Send + Sync>> {
+        return get_text(&self.web, url.clone(), self.timeout).await;
+    }
 }
 
-impl Iterator for SearchResultPages {
-    type Item = SearchResult;
+/// Extracts with `parse_proxy_pairs`, which suits most list pages whatever their layout.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PageExtractor;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        Option::None
-        // calls search_n somehow and returns next SearchResult
+impl ProxyExtractor for PageExtractor {
+    fn extract(&self, _url: &Url, text: &str) -> Result<Vec<ProxyEndpoint>, Box<dyn Error + Send + Sync>> {
+        return parse_proxy_pairs(text);
+    }
+}
+
+/// A `Crawler` composed of a search engine for finding pages, a fetcher for retrieving them
+/// and an extractor for parsing them, any of which can be swapped.
+#[derive(Clone)]
+pub struct SearchCrawler {
+    pub engine: Arc<dyn SearchEngine>,
+    pub fetcher: Arc<dyn Fetcher>,
+    pub extractor: Arc<dyn ProxyExtractor>,
+    /// Result pages `search` goes through.
+    pub max_pages: u16
+}
+
+impl SearchCrawler {
+    pub fn new(engine: Arc<dyn SearchEngine>, fetcher: Arc<dyn Fetcher>, extractor: Arc<dyn ProxyExtractor>) -> SearchCrawler {
+        return SearchCrawler {
+            engine,
+            fetcher,
+            extractor,
+            max_pages: 3
+        }
+    }
+
+    /// Crawls the results of `engine`, fetching the pages they link with `web` and extracting
+    /// proxies from them.
+    pub fn from_engine(engine: Arc<dyn SearchEngine>, web: reqwest::Client) -> SearchCrawler {
+        return SearchCrawler::new(engine, Arc::new(HttpFetcher::new(web)), Arc::new(PageExtractor));
+    }
+
+    pub fn with_engine(mut self, engine: Arc<dyn SearchEngine>) -> SearchCrawler {
+        self.engine = engine;
+        return self;
+    }
+
+    pub fn with_fetcher(mut self, fetcher: Arc<dyn Fetcher>) -> SearchCrawler {
+        self.fetcher = fetcher;
+        return self;
+    }
+
+    pub fn with_extractor(mut self, extractor: Arc<dyn ProxyExtractor>) -> SearchCrawler {
+        self.extractor = extractor;
+        return self;
+    }
+
+    pub fn with_max_pages(mut self, max_pages: u16) -> SearchCrawler {
+        self.max_pages = max_pages;
+        return self;
     }
 }
 
 #[async_trait::async_trait]
-pub trait SearchEngine {
-    async fn search_n(&mut self, text: &str, page: u16) -> Result<SearchResult, Box<dyn Error + Send + Sync>>;
-    async fn search(&mut self, text: &str) -> Result<SearchResult, Box<dyn Error + Send + Sync>>;
-    async fn scrape(&mut self, url: &Url) -> Result<Vec<(Ipv4Addr, u16)>, Box<dyn Error + Send + Sync>>;
+impl Crawler for SearchCrawler {
+    async fn search(&self, text: &str) -> Result<Vec<Url>, Box<dyn Error + Send + Sync>> {
+        let mut pages = self.engine.pages(text, self.max_pages.max(1));
+        let mut urls: Vec<Url> = Vec::new();
+        while let Some(page) = pages.next().await {
+            match page {
+                Ok(page) => {
+                    for url in page.urls {
+                        if !urls.contains(&url) {
+                            urls.push(url);
+                        }
+                    }
+                },
+                // Later pages failing still leaves the results of the earlier ones.
+                Err(e) if urls.is_empty() => return Err(e),
+                Err(_e) => {
+                    #[cfg(feature = "logging")]
+                    log::debug!("Stopping at a failed result page: {:?}", _e);
+                    break;
+                }
+            }
+        }
+
+        return Ok(urls);
+    }
+
+    async fn scrape_proxies(&self, url: &Url) -> Result<Vec<ProxyEndpoint>, Box<dyn Error + Send + Sync>> {
+        let text = self.fetcher.fetch(url).await?;
+        return self.extractor.extract(url, &text);
+    }
 }
 
 // for sync issues: #[async_trait::async_trait(?Send)]
 #[async_trait::async_trait]
 pub trait Crawler {
     async fn search(&self, text: &str) -> Result<Vec<Url>, Box<dyn Error + Send + Sync>>;
-    async fn scrape_proxies(&self, url: &Url) -> Result<Vec<(Ipv4Addr, u16)>, Box<dyn Error + Send + Sync>>;
+    async fn scrape_proxies(&self, url: &Url) -> Result<Vec<ProxyEndpoint>, Box<dyn Error + Send + Sync>>;
 
-    async fn crawl(&self, search_term: &str, limit: usize) -> Result<Vec<(Ipv4Addr, u16)>, Box<dyn Error + Send + Sync>>  {
+    async fn crawl(&self, search_term: &str, limit: usize) -> Result<Vec<ProxyEndpoint>, Box<dyn Error + Send + Sync>>
+        where Self: Sync {
         #[cfg(feature = "logging")]
         log::debug!("Crawler starting: {}", search_term);
 
-        let guarded_proxies: Arc<Mutex<Vec<(Ipv4Addr, u16)>>> = Arc::new(Mutex::new(Vec::new()));
         let urls = self.search(search_term).await?;
+        let proxies = self.scrape_stream(urls, limit)
+            .map(|(_, proxy)| proxy)
+            .collect::<Vec<_>>().await;
 
-        const CONCURRENCY: usize = 20;
-        for i in (0..urls.len()).filter(|x| x % CONCURRENCY == 0) {
-            {
-                let proxies = guarded_proxies.lock().await;
-                if proxies.len() > limit {
-                    break
-                }
-            }
+        return Ok(proxies);
+    }
 
-            async_scoped::TokioScope::scope_and_block(|s| {
-                for j in i..(i + CONCURRENCY) % urls.len() {
-                    let guarded_proxies_ref = &guarded_proxies;
-                    let url = urls[j].clone();
-                    s.spawn(async move {
-                        tokio::time::sleep(Duration::from_millis((j * 10) as u64)).await;
-                        #[cfg(feature = "logging")]
-                        log::debug!("Crawling url {}", url);
-
-                        let new_proxies = match self.scrape_proxies(&url).await {
-                            Ok(list) => {
-                                if !list.is_empty() {Option::Some(list)}
-                                else {None}
-                            },
-                            Err(_) => None
-                        };
-
-                        if new_proxies.is_none() {
-                            return;
-                        }
+    /// Scrapes `urls` with at most `CRAWL_CONCURRENCY` requests in flight, yielding each new proxy
+    /// with the page it was found on. No further pages are fetched once `limit` proxies were yielded.
+    fn scrape_stream<'a>(&'a self, urls: Vec<Url>, limit: usize) -> BoxStream<'a, (Url, ProxyEndpoint)>
+        where Self: Sync {
+        let mut seen = HashSet::new();
+        return stream::iter(urls)
+            .map(move |url| async move {
+                #[cfg(feature = "logging")]
+                log::debug!("Crawling url {}", url);
+
+                let proxies = self.scrape_proxies(&url).await.unwrap_or_else(|_e| {
+                    #[cfg(feature = "logging")]
+                    log::debug!("Unable to scrape {}: {:?}", url, _e);
+                    Vec::new()
+                });
 
-                        #[cfg(feature = "logging")]
-                        log::debug!("New proxies found: {:?}", new_proxies);
+                #[cfg(feature = "logging")]
+                log::debug!("New proxies found on {}: {:?}", url, proxies);
+                return (url, proxies);
+            })
+            .buffer_unordered(CRAWL_CONCURRENCY)
+            .flat_map(|(url, proxies)| stream::iter(proxies.into_iter().map(move |proxy| (url.clone(), proxy))))
+            .filter(move |(_, proxy)| future::ready(seen.insert(proxy.clone())))
+            .take(limit)
+            .boxed();
+    }
 
-                        let mut proxies = guarded_proxies_ref.lock().await;
-                        proxies.extend(new_proxies.unwrap());
-                    });
+    /// Searches for `search_term` and streams `(source_url, proxy)` pairs as pages are scraped,
+    /// stopping after exactly `limit` distinct proxies.
+    fn crawl_stream<'a>(&'a self, search_term: &'a str, limit: usize) -> BoxStream<'a, (Url, ProxyEndpoint)>
+        where Self: Sync {
+        return stream::once(self.search(search_term))
+            .map(move |urls| match urls {
+                Ok(urls) => self.scrape_stream(urls, limit),
+                Err(_e) => {
+                    #[cfg(feature = "logging")]
+                    log::error!("Search for {:?} failed: {:?}", search_term, _e);
+                    stream::empty().boxed()
                 }
-            });
+            })
+            .flatten()
+            .boxed();
+    }
+}
+
+pub(crate) fn html_unescape(text: &str) -> String {
+    return text.replace("&quot;", "\"")
+        .replace("&#x27;", "'")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&");
+}
+
+/// The unescaped value of attribute `name` in the opening tag `tag`.
+pub(crate) fn html_attribute(tag: &str, name: &str) -> Option<String> {
+    lazy_static::lazy_static! {
+        static ref ATTRIBUTE_PATTERN: Regex = Regex::new(obfstr::obfstr!(r#"([a-zA-Z-]+)=(?:"([^"]*)"|'([^']*)')"#))
+            .expect(obfstr::obfstr!("attribute_pattern construction"));
+    }
+
+    return ATTRIBUTE_PATTERN.captures_iter(tag)
+        .find(|captures| captures[1].eq_ignore_ascii_case(name))
+        .and_then(|captures| captures.get(2).or_else(|| captures.get(3)))
+        .map(|value| html_unescape(value.as_str()));
+}
+
+/// Finds `ip:port`, `[ipv6]:port` and `hostname:port` pairs, with an optional `scheme://` prefix
+/// and credentials in either the `user:pass@host:port` or `host:port:user:pass` layout. Trailing
+/// fields that look like list metadata, as in `ip:port:US:elite`, are not read as credentials.
+pub fn parse_basic_proxy_pair(text: &str) -> Vec<ProxyEndpoint> {
+    lazy_static::lazy_static! {
+        static ref PROXY_PATTERN: Regex = Regex::new(obfstr::obfstr!(concat!(
+            r#"(?:(?P<scheme>[a-zA-Z][a-zA-Z0-9]*)://)?"#,
+            r#"(?:(?P<user>[^\s:@/"'<>]+):(?P<pass>[^\s@/"'<>]+)@)?"#,
+            r#"(?P<host>\[[0-9a-fA-F:.]+\]|[0-9]+\.[0-9]+\.[0-9]+\.[0-9]+|(?:[a-zA-Z0-9-]+\.)+[a-zA-Z]{2,})"#,
+            r#":(?P<port>[0-9]{1,5})\b"#,
+            r#"(?::(?P<suffix>[^\s:@/"'<>]+:[^\s:@/"'<>]+))?"#
+        ))).expect(obfstr::obfstr!("proxy_pattern construction"));
+    }
+
+    let mut proxy_pairs: Vec<ProxyEndpoint> = Vec::new();
+    for captures in PROXY_PATTERN.captures_iter(text) {
+        let host = match ProxyHost::from_str(&captures["host"]) {
+            Ok(host) => host,
+            Err(_) => {continue}
+        };
+
+        // Hostnames followed by a path are links on the page, not proxies.
+        let end = captures.get(0).unwrap().end();
+        if let ProxyHost::Domain(_) = host {
+            if text[end..].starts_with('/') {
+                continue;
+            }
+        }
+
+        let port = match captures["port"].parse::<u16>() {
+            Ok(port) => port,
+            Err(_) => {continue}
         };
 
-        return Ok(Arc::try_unwrap(guarded_proxies).unwrap().into_inner());
+        let mut endpoint = ProxyEndpoint::new(host, port);
+        endpoint.credentials = match (captures.name("user"), captures.name("pass")) {
+            (Some(user), Some(pass)) => decode_credentials(ProxyCredentials::new(user.as_str(), pass.as_str())).ok(),
+            _ => captures.name("suffix").and_then(|suffix| suffix_credentials(suffix.as_str()).ok().flatten()),
+        };
+
+        if let Some(scheme) = captures.name("scheme") {
+            match SupportedProtocols::from_str(scheme.as_str()) {
+                Ok(scheme) => endpoint.scheme = Some(scheme),
+                Err(_) => {continue}
+            }
+        }
+
+        if !proxy_pairs.contains(&endpoint) {
+            proxy_pairs.push(endpoint)
+        }
     }
 
+    return proxy_pairs;
 }
 
-pub fn parse_basic_proxy_pair(text: &str) -> Vec<(Ipv4Addr, u16)> {
+fn parse_html_proxy_pair(text: &str) -> Vec<ProxyEndpoint> {
     lazy_static::lazy_static! {
-        static ref IP_PORT_PATTERN: Regex = Regex::new(obfstr::obfstr!(r#"([0-9]+\.[0-9]+\.[0-9]+\.[0-9]+):([0-9]+)"#))
-            .expect(obfstr::obfstr!("ip_port_pattern construction"));
+        static ref HOST_PORT_HTML_PATTERN: Regex = Regex::new(obfstr::obfstr!(r#"<td>\[?([0-9a-zA-Z.:-]+)\]?</td>[\n\r\t ]*<td>([0-9]+)[\n\r\t ]*</td>"#))
+            .expect(obfstr::obfstr!("host_port_html_pattern construction"));
     }
 
-    let mut proxy_pairs: Vec<(Ipv4Addr, u16)> = Vec::new();
-    let matches: regex::Matches = IP_PORT_PATTERN.find_iter(text);
-    for m in matches {
-        let split = m.as_str().split(":").collect::<Vec<&str>>();
-        let ip = match Ipv4Addr::from_str(split[0]) {
-            Ok(ip) => ip,
+    let mut proxy_pairs: Vec<ProxyEndpoint> = Vec::new();
+    for captures in HOST_PORT_HTML_PATTERN.captures_iter(text) {
+        // Single-label names in table cells are far more often countries than hosts.
+        let host = match ProxyHost::from_str(&captures[1]) {
+            Ok(ProxyHost::Domain(domain)) if !is_valid_domain(&domain) => {continue}
+            Ok(host) => host,
             Err(_) => {continue}
         };
 
-        let port = match split[1].parse::<u16>() {
+        let port = match u16::from_str(&captures[2]) {
             Ok(port) => port,
             Err(_) => {continue}
         };
 
-        if !proxy_pairs.contains(&(ip, port)) {
-            proxy_pairs.push((ip, port))
+        let endpoint = ProxyEndpoint::new(host, port);
+        if !proxy_pairs.contains(&endpoint) {
+            proxy_pairs.push(endpoint)
         }
     }
 
     return proxy_pairs;
 }
 
+/// Proxies in `host:port` form anywhere in `text` plus those in `<td>host</td><td>port</td>` table rows.
+pub fn parse_proxy_pairs(text: &str) -> Result<Vec<ProxyEndpoint>, Box<dyn Error + Send + Sync>> {
+    let mut proxy_pairs: Vec<ProxyEndpoint> = Vec::new();
+    for endpoint in parse_basic_proxy_pair(text).into_iter().chain(parse_html_proxy_pair(text)) {
+        if !proxy_pairs.contains(&endpoint) {
+            proxy_pairs.push(endpoint);
+        }
+    }
+
+    return Ok(proxy_pairs);
+}
+
+
 pub async fn public_ip() -> Result<(StatusCode, String), Box<dyn Error + Send + Sync>> {
     return public_ip_from(&reqwest::ClientBuilder::new().build()?).await;
 }
@@ -157,6 +411,128 @@ pub async fn public_ip_from(builder: &reqwest::Client) -> Result<(StatusCode, St
 mod tests {
     use super::*;
     use std::error::Error;
+    use std::net::Ipv4Addr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Every page lists ten proxies unique to that page.
+    struct FakeCrawler {
+        scraped: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl Crawler for FakeCrawler {
+        async fn search(&self, _text: &str) -> Result<Vec<Url>, Box<dyn Error + Send + Sync>> {
+            return Ok((0..100).map(|i| Url::from_str(&format!("http://lists.example.com/{}", i)).unwrap()).collect());
+        }
+
+        async fn scrape_proxies(&self, url: &Url) -> Result<Vec<ProxyEndpoint>, Box<dyn Error + Send + Sync>> {
+            self.scraped.fetch_add(1, Ordering::SeqCst);
+            let page = url.path()[1..].parse::<u8>()?;
+            return Ok((0..10).map(|i| ProxyEndpoint::from((Ipv4Addr::new(10, 0, page, i), 8080))).collect());
+        }
+    }
+
+    // Five pages of one result each.
+    #[derive(Default)]
+    struct FakeEngine {
+        fetched: std::sync::Mutex<Vec<u16>>,
+    }
+
+    #[async_trait::async_trait]
+    impl SearchEngine for FakeEngine {
+        async fn search_n(&self, _text: &str, page: u16) -> Result<SearchResult, Box<dyn Error + Send + Sync>> {
+            self.fetched.lock().unwrap().push(page);
+            return Ok(SearchResult {
+                urls: vec![Url::from_str(&format!("http://lists.example.com/{}", page))?],
+                page,
+                has_next: page < 4
+            });
+        }
+    }
+
+    // Serves every page as its own URL path, e.g. `10.0.0.1:8080` for `http://10.0.0.1:8080`.
+    struct EchoFetcher;
+
+    #[async_trait::async_trait]
+    impl Fetcher for EchoFetcher {
+        async fn fetch(&self, url: &Url) -> Result<String, Box<dyn Error + Send + Sync>> {
+            return Ok(url.path()[1..].replace('/', "\n"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pages_stop_at_cap_or_last_page() {
+        let engine = FakeEngine::default();
+        assert_eq!(engine.pages("free proxy list", 3).count().await, 3);
+        assert_eq!(engine.pages("free proxy list", 10).count().await, 5);
+        assert_eq!(*engine.fetched.lock().unwrap(), [0, 1, 2, 0, 1, 2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_search_crawler_composes() -> Result<(), Box<dyn Error + Send + Sync>> {
+        struct FixedEngine;
+
+        #[async_trait::async_trait]
+        impl SearchEngine for FixedEngine {
+            async fn search_n(&self, _text: &str, page: u16) -> Result<SearchResult, Box<dyn Error + Send + Sync>> {
+                return Ok(SearchResult {
+                    urls: vec![Url::from_str(&format!("http://lists.example.com/10.0.0.{}:8080/10.0.0.9:3128", page))?],
+                    page,
+                    has_next: true
+                });
+            }
+        }
+
+        let crawler = SearchCrawler::new(Arc::new(FixedEngine), Arc::new(EchoFetcher), Arc::new(PageExtractor))
+            .with_max_pages(2);
+        let mut found = crawler.crawl("free proxy list", 100).await?.iter().map(|proxy| proxy.to_string()).collect::<Vec<_>>();
+        found.sort();
+        assert_eq!(found, ["10.0.0.0:8080", "10.0.0.1:8080", "10.0.0.9:3128"]);
+
+        // Swapping in another extractor leaves the search and fetching alone.
+        let crawler = crawler.with_extractor(Arc::new(crate::sources::SourceExtractor::Plain));
+        assert_eq!(crawler.crawl("free proxy list", 100).await?.len(), 3);
+        return Ok(());
+    }
+
+    #[tokio::test]
+    async fn test_crawl_stream_limit() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let crawler = FakeCrawler { scraped: AtomicUsize::new(0) };
+        let found = crawler.crawl_stream("free proxy list", 15).collect::<Vec<_>>().await;
+        assert_eq!(found.len(), 15);
+        assert!(crawler.scraped.load(Ordering::SeqCst) <= CRAWL_CONCURRENCY);
+
+        assert_eq!(crawler.crawl("free proxy list", 1000).await?.len(), 1000);
+        return Ok(());
+    }
+
+    #[test]
+    fn test_parse_proxy_pairs() {
+        assert_eq!(
+            parse_proxy_pairs("127.0.0.1:8080 and 192.168.1.1:5554").unwrap(),
+            [
+                ProxyEndpoint::from_str("127.0.0.1:8080").unwrap(),
+                ProxyEndpoint::from_str("192.168.1.1:5554").unwrap()
+            ]
+        );
+        assert_eq!(
+            parse_proxy_pairs(r#"<td>127.0.0.1</td>
+            <td>8080</td>
+            <td>95.104.54.227</td>
+            <td>42119</td>
+            <td>2001:db8::1</td>
+            <td>3128</td>
+            <td>proxy.example.net</td>
+            <td>80</td>
+            "#).unwrap(),
+            [
+                ProxyEndpoint::from_str("127.0.0.1:8080").unwrap(),
+                ProxyEndpoint::from_str("95.104.54.227:42119").unwrap(),
+                ProxyEndpoint::from_str("[2001:db8::1]:3128").unwrap(),
+                ProxyEndpoint::from_str("proxy.example.net:80").unwrap()
+            ]
+        );
+    }
 
     #[test]
     fn test_parse_basic_proxy_pair() -> Result<(), Box<dyn Error + Send + Sync>> {
@@ -165,11 +541,51 @@ mod tests {
         assert_eq!(
             parse_basic_proxy_pair(test_string),
             [
-                (Ipv4Addr::from_str("127.0.0.1")?, 8080),
-                (Ipv4Addr::from_str("192.168.1.1")?, 1234)
+                ProxyEndpoint::from_str("127.0.0.1:8080")?,
+                ProxyEndpoint::from_str("192.168.1.1:1234")?
+            ]
+        );
+
+        let test_string = r#"[2001:db8::1]:8080 proxy.example.net:3128
+        socks5://10.0.0.1:1080 see https://example.com:443/list.txt"#;
+        assert_eq!(
+            parse_basic_proxy_pair(test_string),
+            [
+                ProxyEndpoint::from_str("[2001:db8::1]:8080")?,
+                ProxyEndpoint::from_str("proxy.example.net:3128")?,
+                ProxyEndpoint::from_str("socks5://10.0.0.1:1080")?
             ]
         );
 
+        let test_string = r#"alice:secret@10.0.0.2:3128
+        10.0.0.3:8080:bob:hunter2"#;
+        assert_eq!(
+            parse_basic_proxy_pair(test_string),
+            [
+                ProxyEndpoint::from_str("10.0.0.2:3128")?.with_credentials("alice", "secret"),
+                ProxyEndpoint::from_str("10.0.0.3:8080")?.with_credentials("bob", "hunter2")
+            ]
+        );
+
+        let test_string = r#"10.0.0.4:8080:US:elite
+        10.0.0.5:3128:DE:anonymous 10.0.0.6:1080:socks5:H 10.0.0.7:8080:bob:x
+        carol:p%40ss@10.0.0.8:3128"#;
+        assert_eq!(
+            parse_basic_proxy_pair(test_string),
+            [
+                ProxyEndpoint::from_str("10.0.0.4:8080")?,
+                ProxyEndpoint::from_str("10.0.0.5:3128")?,
+                ProxyEndpoint::from_str("10.0.0.6:1080")?,
+                ProxyEndpoint::from_str("10.0.0.7:8080")?.with_credentials("bob", "x"),
+                ProxyEndpoint::from_str("10.0.0.8:3128")?.with_credentials("carol", "p@ss")
+            ]
+        );
+
+        // The list parser and `ProxyEndpoint::from_str` read every layout alike.
+        for text in ["10.0.0.4:8080:US:elite", "10.0.0.7:8080:bob:x", "carol:p%40ss@10.0.0.8:3128", "10.0.0.9:8080:dave:p%3Ass"] {
+            assert_eq!(parse_basic_proxy_pair(text), [ProxyEndpoint::from_str(text)?]);
+        }
+
         return Ok(());
     }
 }
\ No newline at end of file
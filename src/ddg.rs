@@ -1,12 +1,54 @@
 use reqwest::Url;
+use std::collections::HashMap;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use std::net::Ipv4Addr;
 use regex::Regex;
 use std::error::Error;
-use crate::proxy::ProxyManager;
-use crate::{random_user_agent, Crawler};
-use crate::crawler::parse_basic_proxy_pair;
+use crate::random_user_agent;
+use crate::crawler::{html_attribute, SearchCrawler, SearchEngine, SearchResult};
+
+/// Fields of a DuckDuckGo lite search form. Lite has no page parameter; the next page is
+/// requested by posting the hidden fields of the "Next Page" form on the current one.
+type SearchForm = Vec<(String, String)>;
+
+fn encode_form(form: &SearchForm) -> String {
+    // `Url` carries the `application/x-www-form-urlencoded` serializer reqwest leaves out.
+    let mut url = Url::from_str("http://localhost/").unwrap();
+    url.query_pairs_mut().extend_pairs(form);
+    return url.query().unwrap_or_default().to_string();
+}
+
+/// The hidden fields of the "Next Page" form, if the page has one.
+fn parse_next_form(text: &str) -> Option<SearchForm> {
+    lazy_static::lazy_static! {
+        static ref FORM_PATTERN: Regex = Regex::new(obfstr::obfstr!(r#"(?is)<form[^>]*>(.*?)</form>"#))
+            .expect(obfstr::obfstr!("form_pattern construction"));
+        static ref INPUT_PATTERN: Regex = Regex::new(obfstr::obfstr!(r#"(?i)<input\b[^>]*>"#))
+            .expect(obfstr::obfstr!("input_pattern construction"));
+    }
+
+    let form = FORM_PATTERN.captures_iter(text)
+        .map(|captures| captures.get(1).unwrap().as_str())
+        .find(|form| form.contains(obfstr::obfstr!("Next Page")))?;
+
+    let mut fields = SearchForm::new();
+    for input in INPUT_PATTERN.find_iter(form) {
+        let input = input.as_str();
+        if !html_attribute(input, "type").is_some_and(|kind| kind.eq_ignore_ascii_case("hidden")) {
+            continue;
+        }
+
+        if let Some(name) = html_attribute(input, "name") {
+            fields.push((name, html_attribute(input, "value").unwrap_or_default()));
+        }
+    }
+
+    return match fields.is_empty() {
+        true => None,
+        false => Some(fields),
+    };
+}
 
 fn filter_result_urls<F>(text: &str, pred: F) -> Result<Vec<reqwest::Url>, Box<dyn Error + Send + Sync>>
     where F: Fn(&Url) -> bool {
@@ -26,150 +68,111 @@ fn filter_result_urls<F>(text: &str, pred: F) -> Result<Vec<reqwest::Url>, Box<d
     return Ok(urls);
 }
 
+#[cfg(test)]
 fn parse_result_urls(text: &str) -> Result<Vec<reqwest::Url>, Box<dyn Error + Send + Sync>>{
     return filter_result_urls(text, |_| -> bool {true});
 }
 
-#[derive(Clone)]
-pub struct DDGCrawler {
+/// DuckDuckGo lite, which needs no JavaScript and serves plain result links.
+pub struct DuckDuckGo {
     web: reqwest::Client,
-    proxy_mgr: ProxyManager,
+    /// Forms that request each page reached so far, per query.
+    forms: Mutex<HashMap<String, Vec<SearchForm>>>,
     pub timeout: Duration
 }
 
-impl DDGCrawler {
-    pub fn new(builder: reqwest::ClientBuilder) -> DDGCrawler {
-        log::debug!("DDGCrawler type size: {}", std::mem::size_of::<DDGCrawler>());
-        return DDGCrawler{
-            web: builder.build().expect(obfstr::obfstr!("Unable to construct reqwest::Client")),
-            proxy_mgr: ProxyManager::new(),
+impl DuckDuckGo {
+    pub fn new(web: reqwest::Client) -> DuckDuckGo {
+        return DuckDuckGo {
+            web,
+            forms: Mutex::new(HashMap::new()),
             timeout: Duration::from_secs(30)
         }
     }
 
-    pub fn from_proxy(proxy: reqwest::Proxy) -> DDGCrawler {
-        return DDGCrawler::new(reqwest::Client::builder()
-            .proxy(proxy)
-            .user_agent(random_user_agent())
-        );
+    pub fn with_timeout(mut self, timeout: Duration) -> DuckDuckGo {
+        self.timeout = timeout;
+        return self;
     }
 
-    pub async fn public_ip(&mut self) -> Result<String, Box<dyn Error + Send + Sync>> {
-        let ip = self.web.get(obfstr::obfstr!("https://api.ipify.org/"))
-            .header(obfstr::obfstr!("User-Agent"), random_user_agent())
-            .header(obfstr::obfstr!("Content-Type"), obfstr::obfstr!("application/x-www-form-urlencoded"))
-            .header(obfstr::obfstr!("Accept-Language"), obfstr::obfstr!("en-US,en;q=0.9"))
-            .timeout(self.timeout)
-            .send().await?
-            .text().await?;
-
-        log::debug!("public_ip = {:?}", ip);
-        return Ok(ip);
-    }
-}
-
-// for sync issues: #[async_trait::async_trait(?Send)]
-#[async_trait::async_trait]
-impl Crawler for DDGCrawler {
-    async fn search(&self, text: &str) -> Result<Vec<reqwest::Url>, Box<dyn Error + Send + Sync>> {
-        let body = std::format!("q={}", text.replace(" ", "+"));
-        log::debug!("body: {}", body);
+    /// Posts `form` to DuckDuckGo lite, returning the result links and the form for the next page.
+    async fn fetch_page(&self, form: &SearchForm) -> Result<(Vec<Url>, Option<SearchForm>), Box<dyn Error + Send + Sync>> {
+        log::debug!("form: {:?}", form);
 
         let response = self.web.post(obfstr::obfstr!("https://html.duckduckgo.com/lite/"))
-            .body(body)
+            .body(encode_form(form))
             .header(obfstr::obfstr!("User-Agent"), random_user_agent())
             .header(obfstr::obfstr!("Content-Type"), obfstr::obfstr!("application/x-www-form-urlencoded"))
             .header(obfstr::obfstr!("Accept-Language"), obfstr::obfstr!("en-US,en;q=0.9"))
             .timeout(self.timeout)
-            .send().await?;
+            .send().await?
+            .error_for_status()?;
 
         let text = response.text().await?;
 
         log::debug!("text: {:?}", text);
-        return Ok(filter_result_urls(text.as_str(), |url: &Url| -> bool {
+        let urls = filter_result_urls(text.as_str(), |url: &Url| -> bool {
             let domain = url.domain();
             return domain.is_some() && !domain.unwrap().contains(obfstr::obfstr!("duckduckgo.com"));
-        })?);
-    }
+        })?;
 
-    async fn scrape_proxies(&self, url: &Url) -> Result<Vec<(Ipv4Addr, u16)>, Box<dyn Error + Send + Sync>> {
-        let response = self.web.get(url.as_str())
-            .header(obfstr::obfstr!("User-Agent"), random_user_agent())
-            .header(obfstr::obfstr!("Content-Type"), obfstr::obfstr!("application/x-www-form-urlencoded"))
-            .header(obfstr::obfstr!("Accept-Language"), obfstr::obfstr!("en-US,en;q=0.9"))
-            .timeout(self.timeout)
-            .send().await?;
-
-        let proxy_pairs = parse_proxy_pairs(response.text().await?.as_str())?;
-        return Ok(proxy_pairs);
+        return Ok((urls, parse_next_form(&text)));
     }
-}
 
-fn parse_html_proxy_pair(text: &str) -> Vec<(Ipv4Addr, u16)> {
-    lazy_static::lazy_static! {
-        static ref IP_PORT_HTML_PATTERN: Regex = Regex::new(obfstr::obfstr!(r#"<td>([0-9]+\.[0-9]+\.[0-9]+\.[0-9]+)</td>[\n\r\t ]*<td>([0-9]+)[\n\r\t ]*</td>"#))
-            .expect(obfstr::obfstr!("ip_port_html_pattern construction"));
+    fn forms(&self) -> std::sync::MutexGuard<'_, HashMap<String, Vec<SearchForm>>> {
+        return self.forms.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
     }
+}
 
-    let mut proxy_pairs: Vec<(Ipv4Addr, u16)> = Vec::new();
-    let matches: regex::Matches = IP_PORT_HTML_PATTERN.find_iter(text);
-    for m in matches {
-        let captures = IP_PORT_HTML_PATTERN.captures(m.as_str())
-            .expect(obfstr::obfstr!("Unable to obtain captures for match"));
-
-        let ip_parse = Ipv4Addr::from_str(&captures[1]);
-        if ip_parse.is_err() {
-            continue;
-        }
-
-        let ip = ip_parse.unwrap();
-        let port_parse = u16::from_str(&captures[2]);
-        if port_parse.is_err() {
-            continue;
-        }
-
-        let port = port_parse.unwrap();
-        if !proxy_pairs.contains(&(ip, port)) {
-            proxy_pairs.push((ip, port))
+#[async_trait::async_trait]
+impl SearchEngine for DuckDuckGo {
+    async fn search_n(&self, text: &str, page: u16) -> Result<SearchResult, Box<dyn Error + Send + Sync>> {
+        let mut index = {
+            let mut forms = self.forms();
+            let forms = forms.entry(text.to_string())
+                .or_insert_with(|| vec![vec![("q".to_string(), text.to_string())]]);
+            (page as usize).min(forms.len() - 1)
+        };
+
+        loop {
+            // Concurrent searches for the same text may have cut the chain of forms short.
+            let form = self.forms().get(text).and_then(|forms| forms.get(index)).cloned()
+                .ok_or_else(|| format!("page {} of {:?} is no longer reachable", index + 1, text))?;
+            let (urls, next) = self.fetch_page(&form).await?;
+            let has_next = next.is_some();
+
+            if let Some(next) = next {
+                let mut forms = self.forms();
+                if let Some(forms) = forms.get_mut(text).filter(|forms| forms.len() > index) {
+                    forms.truncate(index + 1);
+                    forms.push(next);
+                }
+            }
+
+            if index == page as usize {
+                return Ok(SearchResult { urls, page, has_next });
+            }
+
+            if !has_next {
+                return Ok(SearchResult { urls: Vec::new(), page, has_next: false });
+            }
+
+            index += 1;
         }
     }
-
-    return proxy_pairs;
 }
 
-fn parse_proxy_pairs(text: &str) -> Result<Vec<(Ipv4Addr, u16)>, Box<dyn Error + Send + Sync>> {
-    let mut proxy_pairs: Vec<(Ipv4Addr, u16)> = Vec::new();
-    proxy_pairs.extend(parse_basic_proxy_pair(text));
-    proxy_pairs.extend(parse_html_proxy_pair(text));
-    return Ok(proxy_pairs);
+impl SearchCrawler {
+    /// Crawls DuckDuckGo lite results with `web`, extracting proxies from the pages they link.
+    pub fn duckduckgo(web: reqwest::Client) -> SearchCrawler {
+        return SearchCrawler::from_engine(Arc::new(DuckDuckGo::new(web.clone())), web);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_parse_proxy_pairs() {
-        assert_eq!(
-            parse_proxy_pairs("127.0.0.1:8080 and 192.168.1.1:5554").unwrap(),
-            [
-                (Ipv4Addr::from_str("127.0.0.1").unwrap(), 8080),
-                (Ipv4Addr::from_str("192.168.1.1").unwrap(), 5554)
-            ]
-        );
-        assert_eq!(
-            parse_proxy_pairs(r#"<td>127.0.0.1</td>
-            <td>8080</td>
-            <td>95.104.54.227</td>
-            <td>42119</td>
-            "#).unwrap(),
-            [
-                (Ipv4Addr::from_str("127.0.0.1").unwrap(), 8080),
-                (Ipv4Addr::from_str("95.104.54.227").unwrap(), 42119)
-            ]
-        );
-    }
-
     #[test]
     fn test_parse_result_urls() -> Result<(), Box<dyn Error + Send + Sync>>  {
         let html = r#"
@@ -199,4 +202,29 @@ mod tests {
         assert_eq!(urls, [Url::from_str("https://dfir.gov/2010/03/how-to-find-cheese-diy.html")?]);
         return Ok(());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_next_form() {
+        let html = r#"
+<form action="/lite/" method="post">
+  <input type="submit" class='navbutton' value="&lt; Previous Page">
+  <input type="hidden" name="q" value="free proxy list">
+  <input type="hidden" name="s" value="0">
+</form>
+<form action="/lite/" method="post">
+  <input type="submit" class='navbutton' value="Next Page &gt;">
+  <input type="hidden" name="q" value="free proxy list">
+  <input type="hidden" name="s" value="23">
+  <input type="hidden" name="nextParams" value="">
+  <input type="hidden" name="vqd" value="4-1234&amp;5">
+  <input name="kl" value="wt-wt" type="hidden">
+</form>"#;
+
+        let form = parse_next_form(html).unwrap();
+        assert_eq!(form, [
+            ("q", "free proxy list"), ("s", "23"), ("nextParams", ""), ("vqd", "4-1234&5"), ("kl", "wt-wt")
+        ].iter().map(|(name, value)| (name.to_string(), value.to_string())).collect::<Vec<_>>());
+        assert_eq!(encode_form(&form), "q=free+proxy+list&s=23&nextParams=&vqd=4-1234%265&kl=wt-wt");
+        assert_eq!(parse_next_form(&html[..html.find("Next Page").unwrap()]), None);
+    }
+}
@@ -0,0 +1,298 @@
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use reqwest::Url;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use crate::outcome::FailureKind;
+use crate::proxy::{ProxyManager, ProxyTest, SupportedProtocols};
+use crate::select::{ProxyFilter, RoundRobin, SelectionStrategy, Sticky};
+use crate::tunnel::{self, TargetAddr};
+use crate::utility::BoxError;
+
+const BAD_GATEWAY: &[u8] = b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+const BAD_REQUEST: &[u8] = b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+/// Local proxy server that forwards every incoming connection through a proxy from the pool.
+/// One listener speaks both HTTP (CONNECT and absolute-form requests) and SOCKS5, told apart
+/// by the first byte the client sends.
+pub struct Gateway {
+    pool: Arc<Mutex<ProxyManager>>,
+    strategy: std::sync::Mutex<Box<dyn SelectionStrategy>>,
+    pub filter: ProxyFilter,
+    /// Upstream proxies tried per connection, at least one.
+    pub max_attempts: usize,
+    pub connect_timeout: Duration,
+}
+
+impl Gateway {
+    /// A gateway that rotates to the next proxy on every connection.
+    pub fn new(pool: Arc<Mutex<ProxyManager>>) -> Gateway {
+        return Gateway {
+            pool,
+            strategy: std::sync::Mutex::new(Box::new(RoundRobin::default())),
+            filter: ProxyFilter::new(),
+            max_attempts: 3,
+            connect_timeout: Duration::from_secs(10)
+        }
+    }
+
+    pub fn with_strategy(mut self, strategy: Box<dyn SelectionStrategy>) -> Gateway {
+        self.strategy = std::sync::Mutex::new(strategy);
+        return self;
+    }
+
+    /// Keeps each client address on the same upstream proxy for as long as it stays usable.
+    pub fn sticky(self) -> Gateway {
+        return self.with_strategy(Box::new(Sticky::new(Box::new(RoundRobin::default()))));
+    }
+
+    pub fn with_filter(mut self, filter: ProxyFilter) -> Gateway {
+        self.filter = filter;
+        return self;
+    }
+
+    pub fn with_max_attempts(mut self, attempts: usize) -> Gateway {
+        self.max_attempts = attempts;
+        return self;
+    }
+
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Gateway {
+        self.connect_timeout = timeout;
+        return self;
+    }
+
+    /// Accepts connections until the listener fails, handling each on its own task.
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> Result<(), BoxError> {
+        loop {
+            let (client, peer) = listener.accept().await?;
+            let gateway = self.clone();
+            tokio::spawn(async move {
+                if let Err(_e) = gateway.handle(client, peer).await {
+                    #[cfg(feature = "logging")]
+                    log::debug!("gateway connection from {} failed: {}", peer, _e);
+                }
+            });
+        }
+    }
+
+    async fn handle(&self, client: TcpStream, peer: SocketAddr) -> Result<(), BoxError> {
+        let mut first = [0u8; 1];
+        if client.peek(&mut first).await? == 0 {
+            return Ok(());
+        }
+
+        return match first[0] {
+            0x05 => self.handle_socks5(client, peer.ip()).await,
+            _ => self.handle_http(client, peer.ip()).await,
+        };
+    }
+
+    async fn handle_http(&self, mut client: TcpStream, peer: IpAddr) -> Result<(), BoxError> {
+        let head = tunnel::read_http_head(&mut client).await?;
+        let mut headers = [httparse::EMPTY_HEADER; 64];
+        let mut request = httparse::Request::new(&mut headers);
+        request.parse(&head)?;
+
+        let (method, path) = match (request.method, request.path) {
+            (Some(method), Some(path)) => (method, path),
+            _ => {
+                client.write_all(BAD_REQUEST).await?;
+                return Err(obfstr::obfstr!("incomplete http request").into());
+            }
+        };
+
+        if method.eq_ignore_ascii_case("CONNECT") {
+            let target = match TargetAddr::from_str(path) {
+                Ok(target) => target,
+                Err(e) => {
+                    client.write_all(BAD_REQUEST).await?;
+                    return Err(e);
+                }
+            };
+
+            let mut upstream = match self.open_upstream(&target, peer).await {
+                Ok(upstream) => upstream,
+                Err(e) => {
+                    client.write_all(BAD_GATEWAY).await?;
+                    return Err(e);
+                }
+            };
+
+            client.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n").await?;
+            tokio::io::copy_bidirectional(&mut client, &mut upstream).await?;
+            return Ok(());
+        }
+
+        // A plain proxy request names an absolute URL; the target gets it in origin form.
+        let url = match Url::parse(path) {
+            Ok(url) if url.scheme() == "http" && url.host_str().is_some() => url,
+            _ => {
+                client.write_all(BAD_REQUEST).await?;
+                return Err(format!("unsupported proxy request target: {}", path).into());
+            }
+        };
+
+        let target = TargetAddr::new(url.host_str().unwrap_or_default(), url.port_or_known_default().unwrap_or(80));
+        let origin_form = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+
+        let mut forwarded = format!("{} {} HTTP/1.1\r\n", method, origin_form);
+        for header in request.headers.iter() {
+            let name = header.name.to_ascii_lowercase();
+            if name == "proxy-connection" || name == "proxy-authorization" || name == "connection" {
+                continue;
+            }
+            forwarded.push_str(header.name);
+            forwarded.push_str(": ");
+            forwarded.push_str(&String::from_utf8_lossy(header.value));
+            forwarded.push_str("\r\n");
+        }
+
+        // Later requests on this connection may be for other hosts, which this tunnel cannot reach.
+        forwarded.push_str("Connection: close\r\n\r\n");
+
+        let mut upstream = match self.open_upstream(&target, peer).await {
+            Ok(upstream) => upstream,
+            Err(e) => {
+                client.write_all(BAD_GATEWAY).await?;
+                return Err(e);
+            }
+        };
+
+        upstream.write_all(forwarded.as_bytes()).await?;
+        tokio::io::copy_bidirectional(&mut client, &mut upstream).await?;
+        return Ok(());
+    }
+
+    async fn handle_socks5(&self, mut client: TcpStream, peer: IpAddr) -> Result<(), BoxError> {
+        let (target, _) = tunnel::accept_socks5(&mut client, None).await?;
+        let mut upstream = match self.open_upstream(&target, peer).await {
+            Ok(upstream) => upstream,
+            Err(e) => {
+                client.write_all(&tunnel::socks5_reply(0x04)).await?;
+                return Err(e);
+            }
+        };
+
+        client.write_all(&tunnel::socks5_reply(0x00)).await?;
+        tokio::io::copy_bidirectional(&mut client, &mut upstream).await?;
+        return Ok(());
+    }
+
+    /// Opens a tunnel to `target` through the selected proxy, failing over to other proxies
+    /// on connect errors. `peer` keys sticky selection.
+    async fn open_upstream(&self, target: &TargetAddr, peer: IpAddr) -> Result<TcpStream, BoxError> {
+        let key = peer.to_string();
+        let mut filter = self.filter.clone();
+        let mut last_error: Option<BoxError> = None;
+        for _attempt in 1..=self.max_attempts.max(1) {
+            let test = match self.select(&filter, &key).await {
+                Some(test) => test,
+                None => break,
+            };
+            filter = filter.exclude(test.proxy.clone());
+
+            let start = Instant::now();
+            let connected = tokio::time::timeout(self.connect_timeout, tunnel::connect(&test.proxy, &test.protocol, target)).await;
+            match connected.map_err(BoxError::from).and_then(|connected| connected) {
+                Ok(upstream) => {
+                    self.pool.lock().await.report_success(&test.proxy, start.elapsed());
+
+                    #[cfg(feature = "logging")]
+                    log::debug!("gateway tunnel to {} through {} proxy {}", target, test.protocol, test.proxy);
+                    return Ok(upstream);
+                },
+                Err(e) => {
+                    self.pool.lock().await.report_failure(&test.proxy, FailureKind::classify(e.as_ref()));
+
+                    #[cfg(feature = "logging")]
+                    log::debug!("gateway attempt {} through {} failed: {}", _attempt, test.proxy, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        return Err(last_error.unwrap_or_else(|| obfstr::obfstr!("no proxy matches the filter").into()));
+    }
+
+    async fn select(&self, filter: &ProxyFilter, key: &str) -> Option<ProxyTest> {
+        // HTTPS proxies would need TLS to the proxy itself, which tunnels do not speak.
+        let filter = filter.clone().exclude_protocol(SupportedProtocols::Https);
+
+        let mut pool = self.pool.lock().await;
+        let mut strategy = self.strategy.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        return pool.select(strategy.as_mut(), &filter, Some(key));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+    use reqwest::StatusCode;
+    use tokio::io::AsyncReadExt;
+    use crate::endpoint::ProxyEndpoint;
+
+    async fn spawn_gateway(tests: Vec<ProxyTest>) -> Result<(ProxyEndpoint, Arc<Mutex<ProxyManager>>), BoxError> {
+        let mut mgr = ProxyManager::new();
+        for test in tests {
+            mgr.import_test(test);
+        }
+        let pool = Arc::new(Mutex::new(mgr));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let endpoint = ProxyEndpoint::from(listener.local_addr()?);
+        tokio::spawn(Arc::new(Gateway::new(pool.clone())).serve(listener));
+        return Ok((endpoint, pool));
+    }
+
+    fn test_for(proxy: &ProxyEndpoint, protocol: SupportedProtocols) -> ProxyTest {
+        return ProxyTest {
+            proxy: proxy.clone(),
+            protocol,
+            status: StatusCode::OK,
+            text: String::new(),
+            time: SystemTime::now(),
+            rtt: Duration::from_millis(100),
+            anonymity: None
+        };
+    }
+
+    #[tokio::test]
+    async fn test_gateway_end_to_end() -> Result<(), BoxError> {
+        let target = tunnel::spawn_http_server("hello").await?;
+        let (upstream, _) = tunnel::spawn_connect_proxy().await?;
+
+        let dead = TcpListener::bind("127.0.0.1:0").await?;
+        let dead_proxy = ProxyEndpoint::from(dead.local_addr()?);
+        drop(dead);
+
+        // The inner gateway fails over from the dead proxy to the CONNECT upstream.
+        let (inner, inner_pool) = spawn_gateway(vec![
+            test_for(&dead_proxy, SupportedProtocols::Http),
+            test_for(&upstream, SupportedProtocols::Http),
+        ]).await?;
+
+        // CONNECT into the inner gateway.
+        let mut stream = tunnel::connect(&inner, &SupportedProtocols::Http, &TargetAddr::from(target)).await?;
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: target\r\n\r\n").await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        assert!(response.ends_with("hello"));
+        assert_eq!(inner_pool.lock().await.health(&dead_proxy).map(|health| health.consecutive_failures), Some(1));
+
+        // A plain HTTP request into the outer gateway, which reaches the inner one over SOCKS5.
+        let (outer, _) = spawn_gateway(vec![test_for(&inner, SupportedProtocols::Socks5h)]).await?;
+        let client = reqwest::Client::builder()
+            .proxy(outer.to_reqwest_proxy(&SupportedProtocols::Http)?)
+            .build()?;
+        let body = client.get(format!("http://{}/", target)).send().await?.text().await?;
+        assert_eq!(body, "hello");
+        return Ok(());
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime};
+use crate::outcome::FailureKind;
+
+/// Test results kept per endpoint.
+pub const HISTORY_LEN: usize = 32;
+
+/// Weight of the newest sample in the latency average.
+const EWMA_ALPHA: f64 = 0.3;
+
+/// Time without a success after which the freshness factor has halved.
+const FRESHNESS_HALF_LIFE: Duration = Duration::from_secs(30 * 60);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestRecord {
+    pub time: SystemTime,
+    /// Round trip of a successful test.
+    pub rtt: Option<Duration>,
+    /// Classification of a failed test.
+    pub failure: Option<FailureKind>,
+}
+
+impl TestRecord {
+    pub fn is_success(&self) -> bool {
+        return self.failure.is_none();
+    }
+}
+
+/// Rolling record of an endpoint's test results.
+#[derive(Debug, Clone, Default)]
+pub struct ProxyHealth {
+    pub history: VecDeque<TestRecord>,
+    pub ewma_rtt: Option<Duration>,
+    pub last_success: Option<SystemTime>,
+    pub last_failure: Option<SystemTime>,
+    pub consecutive_failures: u32,
+}
+
+impl ProxyHealth {
+    pub fn new() -> ProxyHealth {
+        return ProxyHealth::default();
+    }
+
+    fn push(&mut self, record: TestRecord) {
+        if self.history.len() == HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.history.push_back(record);
+    }
+
+    pub fn record_success(&mut self, time: SystemTime, rtt: Duration) {
+        self.ewma_rtt = Some(match self.ewma_rtt {
+            Some(average) => average.mul_f64(1.0 - EWMA_ALPHA) + rtt.mul_f64(EWMA_ALPHA),
+            None => rtt,
+        });
+        self.last_success = self.last_success.max(Some(time));
+        self.consecutive_failures = 0;
+        self.push(TestRecord { time, rtt: Some(rtt), failure: None });
+    }
+
+    pub fn record_failure(&mut self, time: SystemTime, kind: FailureKind) {
+        self.last_failure = self.last_failure.max(Some(time));
+        self.consecutive_failures += 1;
+        self.push(TestRecord { time, rtt: None, failure: Some(kind) });
+    }
+
+    /// Share of successful tests in the history, `0.0` without history.
+    pub fn success_rate(&self) -> f64 {
+        if self.history.is_empty() {
+            return 0.0;
+        }
+
+        let successes = self.history.iter().filter(|record| record.is_success()).count();
+        return successes as f64 / self.history.len() as f64;
+    }
+
+    /// Combines success rate, average latency and time since the last success into `0.0..=1.0`.
+    pub fn score(&self, now: SystemTime) -> f64 {
+        let last_success = match self.last_success {
+            Some(time) => time,
+            None => return 0.0,
+        };
+
+        // Half at one second of average latency, approaching zero for very slow proxies.
+        let latency = 1.0 / (1.0 + self.ewma_rtt.unwrap_or_default().as_secs_f64());
+
+        let since_success = now.duration_since(last_success).unwrap_or_default();
+        let freshness = 0.5f64.powf(since_success.as_secs_f64() / FRESHNESS_HALF_LIFE.as_secs_f64());
+
+        return self.success_rate() * latency * freshness;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn test_failures_outweigh_one_fast_success() {
+        let now = UNIX_EPOCH + Duration::from_secs(3600);
+
+        let mut flaky = ProxyHealth::new();
+        flaky.record_success(now, Duration::from_millis(50));
+        for _ in 0..10 {
+            flaky.record_failure(now, FailureKind::Timeout);
+        }
+
+        let mut steady = ProxyHealth::new();
+        for _ in 0..10 {
+            steady.record_success(now, Duration::from_millis(800));
+        }
+
+        assert_eq!(flaky.consecutive_failures, 10);
+        assert!(steady.score(now) > flaky.score(now));
+
+        // A success an hour ago counts for a quarter of one just now.
+        let mut stale = ProxyHealth::new();
+        stale.record_success(UNIX_EPOCH, Duration::from_millis(800));
+        assert!((stale.score(now) * 4.0 - steady.score(now)).abs() < 1e-9);
+    }
+}
@@ -1,10 +1,41 @@
+#![allow(clippy::needless_return)]
+
+mod bing;
 mod ddg;
+mod mojeek;
+mod searx;
+mod startpage;
 mod ua;
 mod utility;
+pub mod anonymity;
+pub mod breaker;
+pub mod chain;
+pub mod client;
 pub mod crawler;
+pub mod endpoint;
+pub mod export;
+pub mod gateway;
+pub mod health;
+pub mod import;
+pub mod meta;
+pub mod outcome;
+pub mod probe;
 pub mod proxy;
+pub mod query;
+pub mod revalidate;
+pub mod select;
+pub mod sources;
+pub mod store;
+pub mod tester;
+pub mod tunnel;
+pub mod validator;
 
 pub use ua::random_user_agent;
 pub use crawler::public_ip;
 pub use crawler::Crawler;
-pub use ddg::DDGCrawler;
\ No newline at end of file
+pub use bing::Bing;
+pub use ddg::DuckDuckGo;
+pub use mojeek::Mojeek;
+pub use searx::SearxNg;
+pub use startpage::Startpage;
+pub use endpoint::ProxyEndpoint;
\ No newline at end of file
@@ -1,6 +1,21 @@
+#![allow(clippy::needless_return)]
+
 use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 use env_logger::Env;
-use sockeye::proxy::ProxyManager;
+use sockeye::anonymity::AnonymityLevel;
+use sockeye::export::ExportFormat;
+use sockeye::gateway::Gateway;
+use sockeye::import::{self, ImportFormat};
+use sockeye::proxy::{ProxyManager, SupportedProtocols};
+use sockeye::query::QuerySet;
+use sockeye::revalidate::Revalidator;
+use sockeye::select::ProxyFilter;
+use sockeye::sources::{SourceCrawler, SourceRegistry};
+use sockeye::store::StoreFormat;
 use sockeye::Crawler;
 
 #[tokio::main]
@@ -10,34 +25,161 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>>  {
         .write_style_or("MY_LOG_STYLE", "always");
     env_logger::init_from_env(env);
 
-    let crawler
-        // = sockeye::DDGCrawler::new(reqwest::Client::builder());
-        = sockeye::DDGCrawler::from_proxy(reqwest::Proxy::all("socks5://10.179.205.104:9050").unwrap());
+    let pool_path = PathBuf::from(std::env::var("SOCKEYE_POOL").unwrap_or_else(|_| "proxies.jsonl".to_string()));
+    let pool_format = StoreFormat::from_path(&pool_path)
+        .ok_or_else(|| format!("unknown pool format for {}, expected .jsonl, .ndjson, .csv or .sqlite", pool_path.display()))?;
+    let mut mgr = ProxyManager::new();
+    if pool_path.exists() {
+        mgr.load(&pool_path, pool_format)?;
+    }
 
-    let proxies = match crawler.crawl("free proxy list", 100).await {
-        Ok(proxies) => Some(proxies),
-        Err(_) => None
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let positional = positional_args(&args);
+    match positional.first().map(String::as_str) {
+        // `sockeye serve-gateway [listen address] [--sticky]`
+        Some("serve-gateway") => {
+            let listen = positional.get(1).cloned().unwrap_or_else(|| "127.0.0.1:8899".to_string());
+            let sticky = args.iter().any(|arg| arg == "--sticky");
+            return serve_gateway(mgr, &listen, sticky).await;
+        },
+        // `sockeye export <proxychains|pac|text|json> [output] [--protocol p]... [--max-rtt ms] [--min-anonymity level]`
+        Some("export") => {
+            let format = ExportFormat::from_str(positional.get(1).ok_or("missing export format")?)?;
+            let mut filter = ProxyFilter::new();
+            for protocol in flag_values(&args, "--protocol") {
+                filter = filter.protocol(SupportedProtocols::from_str(protocol)?);
+            }
+            if let Some(max_rtt) = flag_values(&args, "--max-rtt").last() {
+                filter = filter.max_rtt(Duration::from_millis(max_rtt.parse()?));
+            }
+            if let Some(level) = flag_values(&args, "--min-anonymity").last() {
+                filter = filter.min_anonymity(AnonymityLevel::from_str(level)?);
+            }
+
+            match positional.get(2) {
+                Some(output) => mgr.export_to(Path::new(output), format, &filter)?,
+                None => print!("{}", mgr.export(format, &filter)?),
+            }
+            return Ok(());
+        },
+        // `sockeye import <file|-> [--format auto|plain|csv|json|proxychains] [--untested]`
+        Some("import") => {
+            let format = match flag_values(&args, "--format").last() {
+                Some(format) => ImportFormat::from_str(format)?,
+                None => ImportFormat::Auto,
+            };
+            let proxies = import::read_source(positional.get(1).ok_or("missing import source")?, format)?;
+            log::info!("Imported {} proxies", proxies.len());
+
+            if args.iter().any(|arg| arg == "--untested") {
+                // Nothing is contacted here, the revalidator finds out which protocols work.
+                let added = proxies.into_iter().filter(|proxy| mgr.import_untested(proxy.clone())).count();
+                log::info!("Added {} untested proxies", added);
+            } else {
+                let outcome = ProxyManager::test_proxies(&proxies).await?;
+                log::info!("Usable proxies: {:?}", outcome.alive);
+                log::info!("Failures: {:?}", outcome.failure_counts);
+                mgr.record_outcome(&outcome);
+            }
+
+            mgr.save(&pool_path, pool_format)?;
+            return Ok(());
+        },
+        // `sockeye crawl-sources <registry.json> [limit]`
+        Some("crawl-sources") => {
+            let registry_path = PathBuf::from(positional.get(1).ok_or("missing source registry")?);
+            let limit = match positional.get(2) {
+                Some(limit) => limit.parse()?,
+                None => usize::MAX,
+            };
+
+            let crawler = SourceCrawler::new(reqwest::Client::builder(), SourceRegistry::load(&registry_path)?)?;
+            let proxies = crawler.crawl("", limit).await?;
+            log::info!("Sources yielded {} proxies", proxies.len());
+            crawler.registry().save(&registry_path)?;
+
+            let outcome = ProxyManager::test_proxies(&proxies).await?;
+            log::info!("Usable proxies: {:?}", outcome.alive);
+            log::info!("Failures: {:?}", outcome.failure_counts);
+            mgr.record_outcome(&outcome);
+            mgr.save(&pool_path, pool_format)?;
+            return Ok(());
+        },
+        _ => {}
+    }
+
+    let web = reqwest::Client::builder()
+        .proxy(reqwest::Proxy::all("socks5://10.179.205.104:9050").unwrap())
+        .user_agent(sockeye::random_user_agent())
+        .build()?;
+    let crawler = sockeye::crawler::SearchCrawler::from_engine(Arc::new(sockeye::meta::MetaSearch::public_engines(web.clone())), web);
+
+    let queries_path = PathBuf::from(std::env::var("SOCKEYE_QUERIES").unwrap_or_else(|_| "queries.json".to_string()));
+    let mut queries = match queries_path.exists() {
+        true => QuerySet::load(&queries_path)?,
+        false => QuerySet::default_terms(),
     };
 
-    if proxies.is_none() {
-        log::error!("Crawler found no proxies: {:?}", proxies);
+    let proxies = queries.crawl(&crawler, 100).await;
+    queries.save(&queries_path)?;
+
+    if proxies.is_empty() {
+        log::error!("Crawler found no proxies");
         return Ok(())
     }
 
     log::info!("Crawler found proxies: {:?}", proxies);
-    let mut mgr = ProxyManager::new();
-    match ProxyManager::test_proxies(&proxies.unwrap()).await {
-        Ok(proxied_ips) => {
-            log::info!("Usable proxies: {:?}", proxied_ips);
-            for proxy in proxied_ips {
-                mgr.import_test(proxy);
-            }
+    match ProxyManager::test_proxies(&proxies).await {
+        Ok(outcome) => {
+            log::info!("Usable proxies: {:?}", outcome.alive);
+            log::info!("Failures: {:?}", outcome.failure_counts);
+            mgr.record_outcome(&outcome);
         },
         Err(e) => {
             log::error!("Error: {:?}", e);
         }
     }
 
+    log::info!("Exported proxies: {:?}", mgr.export_urls());
+    mgr.save(&pool_path, pool_format)?;
     log::info!("Public IP: {:?}", sockeye::crawler::public_ip().await);
     return Ok(())
-}
\ No newline at end of file
+}
+
+async fn serve_gateway(mgr: ProxyManager, listen: &str, sticky: bool) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let pool = Arc::new(tokio::sync::Mutex::new(mgr));
+    let _revalidator = Revalidator::new().spawn(pool.clone());
+
+    let mut gateway = Gateway::new(pool.clone());
+    if sticky {
+        gateway = gateway.sticky();
+    }
+
+    let listener = tokio::net::TcpListener::bind(listen).await?;
+    log::info!("Gateway listening on {} with {} proxies", listener.local_addr()?, pool.lock().await.len());
+    return Arc::new(gateway).serve(listener).await;
+}
+
+/// Options that take a value; everything else starting with `--` is a switch.
+const VALUE_FLAGS: [&str; 4] = ["--protocol", "--max-rtt", "--min-anonymity", "--format"];
+
+fn positional_args(args: &[String]) -> Vec<String> {
+    let mut positional = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if VALUE_FLAGS.contains(&arg.as_str()) {
+            iter.next();
+        } else if !arg.starts_with("--") {
+            positional.push(arg.clone());
+        }
+    }
+
+    return positional;
+}
+
+fn flag_values<'a>(args: &'a [String], flag: &str) -> Vec<&'a str> {
+    return args.windows(2)
+        .filter(|pair| pair[0] == flag)
+        .map(|pair| pair[1].as_str())
+        .collect();
+}
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use futures::future;
+use reqwest::Url;
+use crate::crawler::{SearchEngine, SearchResult};
+use crate::{Bing, DuckDuckGo, Mojeek, Startpage};
+
+/// Reciprocal rank fusion constant; larger values flatten the advantage of top ranks.
+const RANK_CONSTANT: f64 = 60.0;
+
+/// Query parameters that only track where a click came from.
+const TRACKING_PARAMS: [&str; 11] = [
+    "fbclid", "gclid", "dclid", "msclkid", "yclid", "mc_cid", "mc_eid", "_ga", "_gl", "ref_src", "igshid",
+];
+
+fn is_tracking_param(name: &str) -> bool {
+    return name.starts_with("utm_") || TRACKING_PARAMS.contains(&name);
+}
+
+/// `url` without its fragment and tracking parameters.
+pub fn clean_url(url: &Url) -> Url {
+    let mut cleaned = url.clone();
+    cleaned.set_fragment(None);
+
+    let query = url.query_pairs()
+        .filter(|(name, _)| !is_tracking_param(&name.to_ascii_lowercase()))
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect::<Vec<_>>();
+
+    match query.is_empty() {
+        true => cleaned.set_query(None),
+        false => { cleaned.query_pairs_mut().clear().extend_pairs(query); },
+    }
+
+    return cleaned;
+}
+
+/// The form two result URLs are compared in: cleaned, without a leading `www.` and without a
+/// trailing slash on the path.
+pub fn normalize_url(url: &Url) -> String {
+    let cleaned = clean_url(url);
+    let host = cleaned.host_str().unwrap_or_default();
+    let host = host.strip_prefix("www.").unwrap_or(host);
+
+    let mut normalized = format!("{}://{}", cleaned.scheme(), host);
+    if let Some(port) = cleaned.port() {
+        normalized.push_str(&format!(":{}", port));
+    }
+
+    normalized.push_str(cleaned.path().trim_end_matches('/'));
+    if let Some(query) = cleaned.query() {
+        normalized.push('?');
+        normalized.push_str(query);
+    }
+
+    return normalized;
+}
+
+/// Merges ranked URL lists by reciprocal rank fusion, so URLs several engines rank highly come
+/// first. Duplicates are compared by `normalize_url` and kept in their cleaned form.
+pub fn merge_ranked(lists: &[Vec<Url>]) -> Vec<Url> {
+    let mut merged: Vec<(Url, f64)> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for list in lists {
+        for (rank, url) in list.iter().enumerate() {
+            let score = 1.0 / (RANK_CONSTANT + rank as f64 + 1.0);
+            let key = normalize_url(url);
+            match positions.get(&key) {
+                Some(&position) => merged[position].1 += score,
+                None => {
+                    positions.insert(key, merged.len());
+                    merged.push((clean_url(url), score));
+                }
+            }
+        }
+    }
+
+    // The sort is stable, so equal scores keep the order they were first seen in.
+    merged.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    return merged.into_iter().map(|(url, _)| url).collect();
+}
+
+/// Sends each query to several engines at once and merges their results. Engines that fail
+/// are skipped; the search only fails when all of them do. Engines that ran out of pages for a
+/// query are not asked for that query's later pages.
+#[derive(Clone, Default)]
+pub struct MetaSearch {
+    engines: Vec<Arc<dyn SearchEngine>>,
+    /// The page each engine reported as its last, per query, indexed like `engines`.
+    last_pages: Arc<Mutex<HashMap<String, Vec<Option<u16>>>>>,
+}
+
+impl MetaSearch {
+    pub fn new() -> MetaSearch {
+        return MetaSearch::default();
+    }
+
+    /// DuckDuckGo lite, Bing, Mojeek and Startpage, all through `web`.
+    pub fn public_engines(web: reqwest::Client) -> MetaSearch {
+        return MetaSearch::new()
+            .with_engine(Arc::new(DuckDuckGo::new(web.clone())))
+            .with_engine(Arc::new(Bing::new(web.clone())))
+            .with_engine(Arc::new(Mojeek::new(web.clone())))
+            .with_engine(Arc::new(Startpage::new(web)));
+    }
+
+    pub fn with_engine(mut self, engine: Arc<dyn SearchEngine>) -> MetaSearch {
+        self.engines.push(engine);
+        return self;
+    }
+
+    pub fn len(&self) -> usize {
+        return self.engines.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.engines.is_empty();
+    }
+
+    fn last_pages(&self) -> std::sync::MutexGuard<'_, HashMap<String, Vec<Option<u16>>>> {
+        return self.last_pages.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    }
+}
+
+#[async_trait::async_trait]
+impl SearchEngine for MetaSearch {
+    async fn search_n(&self, text: &str, page: u16) -> Result<SearchResult, Box<dyn Error + Send + Sync>> {
+        let last_pages = self.last_pages().get(text).cloned().unwrap_or_default();
+        let engines = self.engines.iter().enumerate()
+            .filter(|(index, _)| last_pages.get(*index).copied().flatten().is_none_or(|last| page <= last))
+            .collect::<Vec<_>>();
+
+        if engines.is_empty() && !self.engines.is_empty() {
+            return Ok(SearchResult { urls: Vec::new(), page, has_next: false });
+        }
+
+        let results = future::join_all(engines.iter().map(|(_, engine)| engine.search_n(text, page))).await;
+
+        let mut lists = Vec::new();
+        let mut has_next = false;
+        let mut last_error = None;
+        for ((index, _), result) in engines.into_iter().zip(results) {
+            match result {
+                Ok(result) => {
+                    let mut last_pages = self.last_pages();
+                    let last_pages = last_pages.entry(text.to_string()).or_default();
+                    last_pages.resize(last_pages.len().max(self.engines.len()), None);
+                    last_pages[index] = match result.has_next {
+                        true => None,
+                        false => Some(page),
+                    };
+
+                    has_next |= result.has_next;
+                    lists.push(result.urls);
+                },
+                Err(e) => {
+                    #[cfg(feature = "logging")]
+                    log::debug!("search engine failed on page {} of {:?}: {:?}", page, text, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        if lists.is_empty() {
+            return Err(last_error.unwrap_or_else(|| obfstr::obfstr!("no search engines configured").into()));
+        }
+
+        return Ok(SearchResult { urls: merge_ranked(&lists), page, has_next });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct FixedEngine {
+        urls: Vec<&'static str>,
+    }
+
+    #[async_trait::async_trait]
+    impl SearchEngine for FixedEngine {
+        async fn search_n(&self, _text: &str, page: u16) -> Result<SearchResult, Box<dyn Error + Send + Sync>> {
+            if self.urls.is_empty() {
+                return Err(obfstr::obfstr!("rate limited").into());
+            }
+
+            return Ok(SearchResult {
+                urls: self.urls.iter().map(|url| Url::from_str(url)).collect::<Result<_, _>>()?,
+                page,
+                has_next: false
+            });
+        }
+    }
+
+    // Has `pages` pages of one result each and counts the pages it was asked for.
+    struct PagedEngine {
+        name: &'static str,
+        pages: u16,
+        calls: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl SearchEngine for PagedEngine {
+        async fn search_n(&self, _text: &str, page: u16) -> Result<SearchResult, Box<dyn Error + Send + Sync>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            return Ok(SearchResult {
+                urls: vec![Url::from_str(&format!("https://{}.example/{}", self.name, page))?],
+                page,
+                has_next: page + 1 < self.pages
+            });
+        }
+    }
+
+    #[test]
+    fn test_normalize_url() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let url = Url::from_str("https://www.Example.com/list/?utm_source=bing&country=DE&fbclid=x#top")?;
+        assert_eq!(clean_url(&url).as_str(), "https://www.example.com/list/?country=DE");
+        assert_eq!(normalize_url(&url), "https://example.com/list?country=DE");
+        assert_eq!(normalize_url(&Url::from_str("https://example.com/")?), "https://example.com");
+
+        // `ref` often names a list or branch rather than a referrer.
+        let url = Url::from_str("https://lists.example/raw?ref=main&ref_src=twsrc")?;
+        assert_eq!(clean_url(&url).as_str(), "https://lists.example/raw?ref=main");
+        return Ok(());
+    }
+
+    #[tokio::test]
+    async fn test_meta_search_merges_and_tolerates_failures() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let meta = MetaSearch::new()
+            .with_engine(Arc::new(FixedEngine { urls: vec!["https://a.example/", "https://www.b.example/list?utm_medium=x", "https://c.example/"] }))
+            .with_engine(Arc::new(FixedEngine { urls: vec![] }))
+            .with_engine(Arc::new(FixedEngine { urls: vec!["https://b.example/list#proxies", "https://d.example/"] }));
+
+        let result = meta.search("free proxy list").await?;
+        assert_eq!(result.urls.iter().map(Url::as_str).collect::<Vec<_>>(), [
+            "https://www.b.example/list", "https://a.example/", "https://d.example/", "https://c.example/"
+        ]);
+
+        let failing = MetaSearch::new().with_engine(Arc::new(FixedEngine { urls: vec![] }));
+        assert!(failing.search("free proxy list").await.is_err());
+        return Ok(());
+    }
+
+    #[tokio::test]
+    async fn test_meta_search_skips_exhausted_engines() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let short = Arc::new(PagedEngine { name: "short", pages: 1, calls: AtomicUsize::new(0) });
+        let long = Arc::new(PagedEngine { name: "long", pages: 2, calls: AtomicUsize::new(0) });
+        let meta = MetaSearch::new().with_engine(short.clone()).with_engine(long.clone());
+
+        assert!(meta.search_n("free proxy list", 0).await?.has_next);
+        let result = meta.search_n("free proxy list", 1).await?;
+        assert_eq!(result.urls.iter().map(Url::as_str).collect::<Vec<_>>(), ["https://long.example/1"]);
+        assert!(!result.has_next);
+
+        let result = meta.search_n("free proxy list", 2).await?;
+        assert!(result.urls.is_empty() && !result.has_next);
+        assert_eq!((short.calls.load(Ordering::SeqCst), long.calls.load(Ordering::SeqCst)), (1, 2));
+
+        // Other queries and a restart from the first page ask every engine again.
+        meta.search_n("socks5 list", 1).await?;
+        meta.search_n("free proxy list", 0).await?;
+        assert_eq!((short.calls.load(Ordering::SeqCst), long.calls.load(Ordering::SeqCst)), (3, 4));
+        return Ok(());
+    }
+}
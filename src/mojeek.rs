@@ -0,0 +1,104 @@
+use reqwest::Url;
+use std::str::FromStr;
+use std::time::Duration;
+use regex::Regex;
+use std::error::Error;
+use crate::crawler::{get_text, html_attribute, SearchEngine, SearchResult};
+
+/// Results shown per page, which the `s` parameter counts in.
+const PAGE_SIZE: u32 = 10;
+
+fn parse_result_urls(text: &str) -> Vec<Url> {
+    lazy_static::lazy_static! {
+        static ref MOJEEK_RESULT_PATTERN: Regex = Regex::new(obfstr::obfstr!(r#"(?i)<a\b[^>]*\bclass="title"[^>]*>"#))
+            .expect(obfstr::obfstr!("mojeek_result_pattern construction"));
+    }
+
+    let mut urls: Vec<Url> = Vec::new();
+    for anchor in MOJEEK_RESULT_PATTERN.find_iter(text) {
+        let url = match html_attribute(anchor.as_str(), "href").and_then(|href| Url::from_str(&href).ok()) {
+            Some(url) => url,
+            None => continue,
+        };
+
+        let domain = url.domain().unwrap_or_default();
+        if !domain.is_empty() && !domain.ends_with(obfstr::obfstr!("mojeek.com")) && !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+
+    return urls;
+}
+
+/// Whether the pagination links to the results after `page`.
+fn has_next_page(text: &str, page: u16) -> bool {
+    return text.contains(&format!("s={}\"", (u32::from(page) + 1) * PAGE_SIZE + 1));
+}
+
+/// Mojeek, which runs its own index rather than reselling another engine's.
+pub struct Mojeek {
+    web: reqwest::Client,
+    pub timeout: Duration
+}
+
+impl Mojeek {
+    pub fn new(web: reqwest::Client) -> Mojeek {
+        return Mojeek {
+            web,
+            timeout: Duration::from_secs(30)
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Mojeek {
+        self.timeout = timeout;
+        return self;
+    }
+}
+
+#[async_trait::async_trait]
+impl SearchEngine for Mojeek {
+    async fn search_n(&self, text: &str, page: u16) -> Result<SearchResult, Box<dyn Error + Send + Sync>> {
+        let start = (u32::from(page) * PAGE_SIZE + 1).to_string();
+        let url = Url::parse_with_params(obfstr::obfstr!("https://www.mojeek.com/search"), &[("q", text), ("s", &start)])?;
+        let text = get_text(&self.web, url, self.timeout).await?;
+
+        return Ok(SearchResult {
+            urls: parse_result_urls(&text),
+            page,
+            has_next: has_next_page(&text, page)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_result_urls() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let html = r#"
+<ul class="results-standard">
+  <li class="r1">
+    <a class="ob" href="https://proxies.example.com/http">proxies.example.com/http</a>
+    <h2><a class="title" href="https://proxies.example.com/http">HTTP Proxy List</a></h2>
+    <p class="s">Fresh <strong>free</strong> <strong>proxy</strong> <strong>list</strong>.</p>
+  </li>
+  <li class="r2">
+    <h2><a href="https://example.net/socks?type=5&amp;sort=speed" class="title">SOCKS Proxies</a></h2>
+  </li>
+  <li class="r3"><h2><a class="title" href="https://www.mojeek.com/about">About</a></h2></li>
+</ul>
+<div class="pagination"><ul>
+  <li><a href="/search?q=free+proxy+list&amp;s=11">2</a></li>
+  <li><a class="next" href="/search?q=free+proxy+list&amp;s=11">Next</a></li>
+</ul></div>"#;
+
+        assert_eq!(parse_result_urls(html), [
+            Url::from_str("https://proxies.example.com/http")?,
+            Url::from_str("https://example.net/socks?type=5&sort=speed")?
+        ]);
+        assert!(has_next_page(html, 0));
+        assert!(!has_next_page(html, 1));
+        return Ok(());
+    }
+}
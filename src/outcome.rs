@@ -0,0 +1,223 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::time::SystemTime;
+use crate::endpoint::ProxyEndpoint;
+use crate::proxy::{ProxyTest, SupportedProtocols};
+use crate::validator::ValidationError;
+
+/// How the connectors and our own tunnels report rejected or missing proxy credentials.
+const AUTH_PHRASES: [&str; 6] = [
+    "proxy authentication required",
+    "proxy authorization required",
+    "credentials not accepted",
+    "does not support user/pass authentication",
+    "rejected our credentials",
+    "requires an auth method we do not offer",
+];
+
+/// Proxy protocols whose own handshakes fail with a bare "handshake" message, which is not a TLS problem.
+const PROXY_PROTOCOL_PHRASES: [&str; 2] = ["socks", "tunnel"];
+
+/// Why a proxy test failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum FailureKind {
+    /// No protocol handshake was answered, so no request was attempted.
+    NoProtocol,
+    ConnectRefused,
+    ConnectionReset,
+    Timeout,
+    Tls,
+    /// The proxy answered 407 or rejected our SOCKS credentials.
+    AuthRequired,
+    BadStatus,
+    BadBody,
+    TooSlow,
+    /// The protocol cannot be tested in this setup, e.g. HTTPS behind an upstream chain.
+    /// Says nothing about the proxy, so it does not count against its health.
+    Unsupported,
+    Other,
+}
+
+impl fmt::Display for FailureKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FailureKind::NoProtocol => "no-protocol",
+            FailureKind::ConnectRefused => "connect-refused",
+            FailureKind::ConnectionReset => "connection-reset",
+            FailureKind::Timeout => "timeout",
+            FailureKind::Tls => "tls",
+            FailureKind::AuthRequired => "auth-required",
+            FailureKind::BadStatus => "bad-status",
+            FailureKind::BadBody => "bad-body",
+            FailureKind::TooSlow => "too-slow",
+            FailureKind::Unsupported => "unsupported",
+            FailureKind::Other => "other",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FailureKind {
+    /// Walks the error's source chain for the most specific cause we recognise.
+    pub fn classify(error: &(dyn Error + 'static)) -> FailureKind {
+        let mut current: Option<&(dyn Error + 'static)> = Some(error);
+        let mut fallback = FailureKind::Other;
+        while let Some(err) = current {
+            if let Some(validation) = err.downcast_ref::<ValidationError>() {
+                return match validation {
+                    ValidationError::UnexpectedStatus(status) if status.as_u16() == 407 => FailureKind::AuthRequired,
+                    ValidationError::UnexpectedStatus(_) => FailureKind::BadStatus,
+                    ValidationError::BodyMismatch | ValidationError::HashMismatch(_) => FailureKind::BadBody,
+                    ValidationError::TooSlow(_) => FailureKind::TooSlow,
+                };
+            }
+
+            if err.is::<tokio::time::error::Elapsed>() {
+                return FailureKind::Timeout;
+            }
+
+            if let Some(io) = err.downcast_ref::<std::io::Error>() {
+                match io.kind() {
+                    std::io::ErrorKind::ConnectionRefused => return FailureKind::ConnectRefused,
+                    std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::UnexpectedEof => return FailureKind::ConnectionReset,
+                    std::io::ErrorKind::TimedOut => return FailureKind::Timeout,
+                    _ => {}
+                }
+            }
+
+            if let Some(reqwest_error) = err.downcast_ref::<reqwest::Error>() {
+                if reqwest_error.is_timeout() {
+                    return FailureKind::Timeout;
+                }
+            }
+
+            // Tunnel and TLS errors from the connector only identify themselves by message.
+            let message = err.to_string().to_ascii_lowercase();
+            let status_407 = message.split(|c: char| !c.is_ascii_digit()).any(|number| number == "407");
+            if status_407 || AUTH_PHRASES.iter().any(|phrase| message.contains(phrase)) {
+                return FailureKind::AuthRequired;
+            }
+
+            let proxy_handshake = PROXY_PROTOCOL_PHRASES.iter().any(|phrase| message.contains(phrase));
+            if message.contains("tls") || message.contains("certificate") || (message.contains("handshake") && !proxy_handshake) {
+                fallback = FailureKind::Tls;
+            }
+
+            current = err.source();
+        }
+
+        return fallback;
+    }
+}
+
+/// A failed attempt at testing `proxy`, `protocol` is `None` when probing found nothing to test.
+#[derive(Debug, Clone)]
+pub struct ProxyFailure {
+    pub proxy: ProxyEndpoint,
+    pub protocol: Option<SupportedProtocols>,
+    pub kind: FailureKind,
+    pub message: String,
+    pub time: SystemTime,
+}
+
+impl ProxyFailure {
+    pub fn new(proxy: &ProxyEndpoint, protocol: Option<&SupportedProtocols>, error: &(dyn Error + 'static)) -> ProxyFailure {
+        return ProxyFailure {
+            proxy: proxy.clone(),
+            protocol: protocol.cloned(),
+            kind: FailureKind::classify(error),
+            message: error.to_string(),
+            time: SystemTime::now()
+        }
+    }
+
+    pub fn unsupported(proxy: &ProxyEndpoint, protocol: &SupportedProtocols) -> ProxyFailure {
+        return ProxyFailure {
+            proxy: proxy.clone(),
+            protocol: Some(protocol.clone()),
+            kind: FailureKind::Unsupported,
+            message: format!("{} proxies cannot be tested through the upstream", protocol),
+            time: SystemTime::now()
+        }
+    }
+
+    pub fn no_protocol(proxy: &ProxyEndpoint) -> ProxyFailure {
+        return ProxyFailure {
+            proxy: proxy.clone(),
+            protocol: None,
+            kind: FailureKind::NoProtocol,
+            message: obfstr::obfstr!("no proxy protocol detected").to_string(),
+            time: SystemTime::now()
+        }
+    }
+}
+
+/// Result of a `test_proxies` run: working proxies, the failed attempts of endpoints that did
+/// not work with any protocol, and how many of those attempts fell into each failure class.
+#[derive(Debug, Clone, Default)]
+pub struct ProxyTestOutcome {
+    pub alive: Vec<ProxyTest>,
+    pub dead: Vec<ProxyFailure>,
+    pub failure_counts: BTreeMap<FailureKind, usize>,
+}
+
+impl ProxyTestOutcome {
+    pub fn new() -> ProxyTestOutcome {
+        return ProxyTestOutcome::default();
+    }
+
+    pub fn record_alive(&mut self, test: ProxyTest) {
+        self.alive.push(test);
+    }
+
+    pub fn record_dead(&mut self, failure: ProxyFailure) {
+        *self.failure_counts.entry(failure.kind).or_insert(0) += 1;
+        self.dead.push(failure);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+
+    #[test]
+    fn test_classify() {
+        let refused = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused");
+        assert_eq!(FailureKind::classify(&refused), FailureKind::ConnectRefused);
+        assert_eq!(
+            FailureKind::classify(&ValidationError::UnexpectedStatus(StatusCode::PROXY_AUTHENTICATION_REQUIRED)),
+            FailureKind::AuthRequired
+        );
+        assert_eq!(
+            FailureKind::classify(&ValidationError::UnexpectedStatus(StatusCode::FORBIDDEN)),
+            FailureKind::BadStatus
+        );
+        assert_eq!(FailureKind::classify(&ValidationError::BodyMismatch), FailureKind::BadBody);
+    }
+
+    #[test]
+    fn test_classify_auth_messages() {
+        let classify = |message: &str| FailureKind::classify(&std::io::Error::other(message.to_string()));
+        assert_eq!(classify("unsuccessful tunnel: HTTP/1.1 407 Proxy Authentication Required"), FailureKind::AuthRequired);
+        assert_eq!(classify("proxy authorization required"), FailureKind::AuthRequired);
+        assert_eq!(classify("credentials not accepted"), FailureKind::AuthRequired);
+        assert_eq!(classify("socks5 proxy rejected our credentials, auth failed"), FailureKind::AuthRequired);
+
+        assert_eq!(classify("invalid URL authority"), FailureKind::Other);
+        assert_eq!(classify("unauthorized host 10.0.0.1:14070"), FailureKind::Other);
+        assert_eq!(classify("tls handshake failed: peer authentication failed"), FailureKind::Tls);
+    }
+
+    #[test]
+    fn test_classify_proxy_handshakes() {
+        let classify = |message: &str| FailureKind::classify(&std::io::Error::other(message.to_string()));
+        assert_eq!(classify("socks handshake failed"), FailureKind::Other);
+        assert_eq!(classify("tunnel handshake failed: unexpected eof"), FailureKind::Other);
+        assert_eq!(classify("handshake failure"), FailureKind::Tls);
+        assert_eq!(classify("invalid peer certificate: UnknownIssuer"), FailureKind::Tls);
+    }
+}
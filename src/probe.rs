@@ -0,0 +1,175 @@
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use crate::chain::ProxyChain;
+use crate::endpoint::ProxyEndpoint;
+use crate::proxy::SupportedProtocols;
+use crate::tunnel::TargetAddr;
+use crate::utility::BoxError;
+
+// SOCKS5 greeting offering "no authentication" and "username/password".
+const SOCKS5_GREETING: [u8; 4] = [0x05, 0x02, 0x00, 0x02];
+
+// SOCKS4 CONNECT to 1.1.1.1:80 with an empty user id.
+const SOCKS4_CONNECT: [u8; 9] = [0x04, 0x01, 0x00, 0x50, 0x01, 0x01, 0x01, 0x01, 0x00];
+
+// Minimal TLS 1.2 ClientHello: no extensions and a handful of common cipher suites.
+// Any TLS server answers it with either a ServerHello or an alert record.
+const TLS_CLIENT_HELLO: [u8; 56] = [
+    0x16, 0x03, 0x01, 0x00, 0x33,
+    0x01, 0x00, 0x00, 0x2f,
+    0x03, 0x03,
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+    0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f,
+    0x00,
+    0x00, 0x08, 0xc0, 0x2f, 0xc0, 0x2b, 0x00, 0x9c, 0x00, 0x2f,
+    0x01, 0x00,
+];
+
+async fn exchange(upstream: &ProxyChain, proxy: &ProxyEndpoint, request: &[u8], reply_len: usize, timeout: Duration) -> Result<Vec<u8>, BoxError> {
+    let exchange = async {
+        let mut stream = upstream.connect(&TargetAddr::new(&proxy.host.to_string(), proxy.port)).await?;
+        stream.write_all(request).await?;
+
+        let mut reply = vec![0u8; reply_len];
+        stream.read_exact(&mut reply).await?;
+        return Ok::<Vec<u8>, BoxError>(reply);
+    };
+
+    return tokio::time::timeout(timeout, exchange).await?;
+}
+
+pub async fn probe_socks5(upstream: &ProxyChain, proxy: &ProxyEndpoint, timeout: Duration) -> bool {
+    return match exchange(upstream, proxy, &SOCKS5_GREETING, 2, timeout).await {
+        Ok(reply) => reply[0] == 0x05 && reply[1] != 0xff,
+        Err(_) => false,
+    };
+}
+
+pub async fn probe_socks4(upstream: &ProxyChain, proxy: &ProxyEndpoint, timeout: Duration) -> bool {
+    // 0x5a granted, 0x5b-0x5d rejected: both mean the port speaks SOCKS4.
+    return match exchange(upstream, proxy, &SOCKS4_CONNECT, 2, timeout).await {
+        Ok(reply) => reply[0] == 0x00 && (0x5a..=0x5d).contains(&reply[1]),
+        Err(_) => false,
+    };
+}
+
+pub async fn probe_http(upstream: &ProxyChain, proxy: &ProxyEndpoint, timeout: Duration) -> bool {
+    let request = obfstr::obfstr!("CONNECT api.ipify.org:443 HTTP/1.1\r\nHost: api.ipify.org:443\r\n\r\n").to_string();
+    return match exchange(upstream, proxy, request.as_bytes(), 5, timeout).await {
+        Ok(reply) => reply == b"HTTP/",
+        Err(_) => false,
+    };
+}
+
+pub async fn probe_tls(upstream: &ProxyChain, proxy: &ProxyEndpoint, timeout: Duration) -> bool {
+    // Handshake (0x16) or alert (0x15) record with a TLS 1.x version.
+    return match exchange(upstream, proxy, &TLS_CLIENT_HELLO, 2, timeout).await {
+        Ok(reply) => (reply[0] == 0x16 || reply[0] == 0x15) && reply[1] == 0x03,
+        Err(_) => false,
+    };
+}
+
+/// Speaks just enough of each proxy protocol's opening exchange to tell which ones the port
+/// understands. The probes run concurrently on separate connections; each detected protocol is
+/// expanded to its family so that e.g. a SOCKS5 port is also tested as SOCKS5h.
+pub async fn detect_protocols(proxy: &ProxyEndpoint, timeout: Duration) -> Vec<SupportedProtocols> {
+    return detect_protocols_through(&ProxyChain::new(), proxy, timeout).await;
+}
+
+/// Like `detect_protocols`, with every probe connection going through `upstream`.
+pub async fn detect_protocols_through(upstream: &ProxyChain, proxy: &ProxyEndpoint, timeout: Duration) -> Vec<SupportedProtocols> {
+    let (socks5, socks4, http, tls) = tokio::join!(
+        probe_socks5(upstream, proxy, timeout),
+        probe_socks4(upstream, proxy, timeout),
+        probe_http(upstream, proxy, timeout),
+        probe_tls(upstream, proxy, timeout)
+    );
+
+    let mut protocols = Vec::new();
+    if socks5 {
+        protocols.extend(SupportedProtocols::Socks5.family());
+    }
+
+    if socks4 {
+        protocols.extend(SupportedProtocols::Socks4.family());
+    }
+
+    if http {
+        protocols.extend(SupportedProtocols::Http.family());
+    }
+
+    if tls {
+        protocols.extend(SupportedProtocols::Https.family());
+    }
+
+    #[cfg(feature = "logging")]
+    log::debug!("detected protocols for {}: {:?}", proxy, protocols);
+    return protocols;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use tokio::net::TcpListener;
+
+    // Fake proxy: answers requests starting with `first_byte` with `reply`, drops every other protocol.
+    async fn spawn_responder(first_byte: u8, reply: &'static [u8]) -> Result<ProxyEndpoint, BoxError> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let proxy = ProxyEndpoint::from(listener.local_addr()?);
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 64];
+                    if let Ok(n) = stream.read(&mut buf).await {
+                        if n > 0 && buf[0] == first_byte {
+                            let _ = stream.write_all(reply).await;
+                        }
+                    }
+                });
+            }
+        });
+
+        return Ok(proxy);
+    }
+
+    #[tokio::test]
+    async fn test_detect_protocols() -> Result<(), BoxError> {
+        let cases: [(u8, &'static [u8], &[SupportedProtocols]); 4] = [
+            (0x05, &[0x05, 0x00], &[SupportedProtocols::Socks5, SupportedProtocols::Socks5h]),
+            (0x04, &[0x00, 0x5b, 0, 0, 0, 0, 0, 0], &[SupportedProtocols::Socks4, SupportedProtocols::Socks4a]),
+            (b'C', b"HTTP/1.1 200 Connection established\r\n\r\n", &[SupportedProtocols::Http]),
+            // A handshake failure alert still shows the port speaks TLS.
+            (0x16, &[0x15, 0x03, 0x03, 0x00, 0x02, 0x02, 0x28], &[SupportedProtocols::Https]),
+        ];
+
+        for (first_byte, reply, expected) in cases {
+            let proxy = spawn_responder(first_byte, reply).await?;
+            assert_eq!(detect_protocols(&proxy, Duration::from_secs(5)).await, expected);
+        }
+
+        return Ok(());
+    }
+
+    #[tokio::test]
+    async fn test_detect_protocols_on_unresponsive_ports() -> Result<(), BoxError> {
+        // Accepts connections and holds them open without ever answering.
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let silent = ProxyEndpoint::from(listener.local_addr()?);
+        tokio::spawn(async move {
+            let mut streams = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                streams.push(stream);
+            }
+        });
+
+        let start = Instant::now();
+        assert!(detect_protocols(&silent, Duration::from_millis(200)).await.is_empty());
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        // Nothing listens on the port once the listener is dropped.
+        let dead = ProxyEndpoint::from(TcpListener::bind("127.0.0.1:0").await?.local_addr()?);
+        assert!(detect_protocols(&dead, Duration::from_secs(5)).await.is_empty());
+        return Ok(());
+    }
+}
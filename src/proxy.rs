@@ -1,27 +1,41 @@
-use std::net::Ipv4Addr;
-use std::time::{Duration, Instant};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use reqwest::StatusCode;
-use std::cmp::{Ordering, min};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::Path;
 use std::error::Error;
-use crate::utility::BoxError;
+use std::str::FromStr;
+use crate::anonymity::AnonymityLevel;
+use crate::breaker::{BreakerPolicy, BreakerState, CircuitBreaker};
+use crate::endpoint::ProxyEndpoint;
+use crate::export::{self, ExportFormat};
+use crate::health::ProxyHealth;
+use crate::outcome::{FailureKind, ProxyFailure, ProxyTestOutcome};
 use crate::random_user_agent;
+use crate::select::{Candidate, ProxyFilter, SelectionStrategy};
+use crate::store::{self, ProxyRecord, StoreFormat};
+use crate::tester::ProxyTester;
+use crate::validator::ProxyValidator;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use futures::stream::BoxStream;
+use crate::utility::BoxError;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct ProxyTest {
-    pub proxy: (Ipv4Addr, u16),
+    pub proxy: ProxyEndpoint,
     pub protocol: SupportedProtocols,
     pub status: StatusCode,
     pub text: String,
-    pub time: Instant,
-    pub rtt: Duration
+    pub time: SystemTime,
+    pub rtt: Duration,
+    pub anonymity: Option<AnonymityLevel>
 }
 
 // `PartialOrd` needs to be implemented as well.
 impl PartialOrd for ProxyTest {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        return self.rtt.partial_cmp(&other.rtt);
+        return Some(self.cmp(other));
     }
 }
 
@@ -31,32 +45,98 @@ impl Ord for ProxyTest {
     }
 }
 
+/// Tests, health and breakers are keyed on endpoints without their scheme, so that `host:port`
+/// and `socks5://host:port` share one history. The protocol lives in `ProxyTest::protocol`.
 #[derive(Clone, Debug)]
 pub struct ProxyManager {
     proxies: Vec<ProxyTest>,
+    /// Endpoints added without a test, each under the scheme it will be tested with.
+    untested: Vec<ProxyEndpoint>,
+    health: HashMap<ProxyEndpoint, ProxyHealth>,
+    breakers: HashMap<ProxyEndpoint, CircuitBreaker>,
+    breaker_policy: BreakerPolicy,
+    last_used: HashMap<(ProxyEndpoint, SupportedProtocols), SystemTime>,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+/// Proxy protocols, named after their URL schemes. `Https` is an HTTP proxy reached over TLS,
+/// `Socks4a` and `Socks5h` have the proxy resolve the target hostname instead of us.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum SupportedProtocols {
-    Http, Socks5
+    Http, Https, Socks4, Socks4a, Socks5, Socks5h
 }
 
-impl ToString for SupportedProtocols {
-    fn to_string(&self) -> String {
+impl SupportedProtocols {
+    pub const ALL: [SupportedProtocols; 6] = [
+        SupportedProtocols::Socks5, SupportedProtocols::Socks5h,
+        SupportedProtocols::Socks4, SupportedProtocols::Socks4a,
+        SupportedProtocols::Http, SupportedProtocols::Https,
+    ];
+
+    /// Variants that share a wire protocol with `self`, e.g. `Socks5` and `Socks5h`.
+    pub fn family(&self) -> Vec<SupportedProtocols> {
         match self {
-            SupportedProtocols::Http => "http".to_string(),
-            SupportedProtocols::Socks5 => "socks5".to_string(),
+            SupportedProtocols::Http => vec![SupportedProtocols::Http],
+            SupportedProtocols::Https => vec![SupportedProtocols::Https],
+            SupportedProtocols::Socks4 | SupportedProtocols::Socks4a
+                => vec![SupportedProtocols::Socks4, SupportedProtocols::Socks4a],
+            SupportedProtocols::Socks5 | SupportedProtocols::Socks5h
+                => vec![SupportedProtocols::Socks5, SupportedProtocols::Socks5h],
         }
     }
 }
 
+impl std::fmt::Display for SupportedProtocols {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SupportedProtocols::Http => write!(f, "http"),
+            SupportedProtocols::Https => write!(f, "https"),
+            SupportedProtocols::Socks4 => write!(f, "socks4"),
+            SupportedProtocols::Socks4a => write!(f, "socks4a"),
+            SupportedProtocols::Socks5 => write!(f, "socks5"),
+            SupportedProtocols::Socks5h => write!(f, "socks5h"),
+        }
+    }
+}
+
+impl FromStr for SupportedProtocols {
+    type Err = BoxError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "http" => Ok(SupportedProtocols::Http),
+            "https" => Ok(SupportedProtocols::Https),
+            "socks4" => Ok(SupportedProtocols::Socks4),
+            "socks4a" => Ok(SupportedProtocols::Socks4a),
+            "socks5" | "socks" => Ok(SupportedProtocols::Socks5),
+            "socks5h" => Ok(SupportedProtocols::Socks5h),
+            _ => Err(format!("unsupported proxy protocol: {}", s).into()),
+        }
+    }
+}
+
+impl Default for ProxyManager {
+    fn default() -> Self {
+        return ProxyManager::new();
+    }
+}
+
 impl ProxyManager {
     pub fn new() -> ProxyManager {
         return ProxyManager {
-            proxies: Vec::new()
+            proxies: Vec::new(),
+            untested: Vec::new(),
+            health: HashMap::new(),
+            breakers: HashMap::new(),
+            breaker_policy: BreakerPolicy::default(),
+            last_used: HashMap::new()
         }
     }
 
+    pub fn with_breaker_policy(mut self, policy: BreakerPolicy) -> ProxyManager {
+        self.breaker_policy = policy;
+        return self;
+    }
+
     // pub fn import_tests<Tests>(&mut self, tests: &mut Tests)
     //     where Tests: Iterator{
     //     for test in tests {
@@ -64,89 +144,552 @@ impl ProxyManager {
     //     }
     // }
 
-    pub fn import_test(&mut self, test: ProxyTest) {
+    pub fn import_test(&mut self, mut test: ProxyTest) {
+        test.proxy = test.proxy.without_scheme();
+        self.health.entry(test.proxy.clone()).or_default()
+            .record_success(test.time, test.rtt);
         self.proxies.push(test);
     }
 
-    pub async fn test_proxy(protocol: &SupportedProtocols, proxy: &(Ipv4Addr, u16)) -> Result<ProxyTest, Box<dyn Error + Send + Sync>> {
-        let scheme = std::format!("{}://{}:{}", protocol.to_string(), proxy.0, proxy.1);
-        let client = reqwest::Client::builder().proxy(
-            reqwest::Proxy::all(scheme)?
-        ).build()?;
-
-        let before_get = Instant::now();
-        let response = client.get(obfstr::obfstr!("https://api.ipify.org/"))
-            .header(obfstr::obfstr!("User-Agent"), random_user_agent())
-            .header(obfstr::obfstr!("Content-Type"), obfstr::obfstr!("application/x-www-form-urlencoded"))
-            .header(obfstr::obfstr!("Accept-Language"), obfstr::obfstr!("en-US,en;q=0.9"))
-            .timeout(Duration::from_secs(30))
-            .send().await?;
-
-        let status = response.status();
-        let text = response.text().await?;
-        let rtt = Instant::now().duration_since(before_get);
-
-        let test = ProxyTest{
-            proxy: proxy.clone(),
-            protocol: protocol.clone(),
-            time: Instant::now(),
-            status, text, rtt
-        };
+    /// Adds `proxy` without testing it, to be tested under its URL scheme by the revalidator.
+    /// Endpoints without a scheme are tested under every protocol their handshake answers to.
+    /// Untested endpoints are never selected or exported. Returns whether the pool changed.
+    pub fn import_untested(&mut self, proxy: ProxyEndpoint) -> bool {
+        let known = self.untested.contains(&proxy) || self.proxies.iter()
+            .any(|test| proxy.scheme.as_ref().is_none_or(|scheme| scheme == &test.protocol) && same_address(&test.proxy, &proxy));
+        if known {
+            return false;
+        }
+
+        self.untested.push(proxy);
+        return true;
+    }
+
+    /// Endpoints waiting for their first test.
+    pub fn untested(&self) -> &[ProxyEndpoint] {
+        return &self.untested;
+    }
+
+    /// Records a failed test in the endpoint's history. The endpoint stays in the pool until evicted.
+    /// `FailureKind::Unsupported` is ignored, the proxy was not actually tried.
+    pub fn record_failure(&mut self, failure: &ProxyFailure) {
+        if failure.kind == FailureKind::Unsupported {
+            return;
+        }
+
+        self.health.entry(failure.proxy.without_scheme()).or_default()
+            .record_failure(failure.time, failure.kind);
+    }
+
+    /// Merges the working proxies of a `test_proxies` run and records its failures.
+    pub fn record_outcome(&mut self, outcome: &ProxyTestOutcome) {
+        for test in &outcome.alive {
+            self.merge_test(test.clone());
+        }
+
+        for failure in &outcome.dead {
+            self.record_failure(failure);
+        }
+    }
+
+    pub fn health(&self, proxy: &ProxyEndpoint) -> Option<&ProxyHealth> {
+        return self.health.get(&proxy.without_scheme());
+    }
+
+    /// Tells the pool that a request through `proxy` worked, closing its breaker.
+    pub fn report_success(&mut self, proxy: &ProxyEndpoint, rtt: Duration) {
+        let proxy = proxy.without_scheme();
+        self.health.entry(proxy.clone()).or_default()
+            .record_success(SystemTime::now(), rtt);
+        self.breakers.entry(proxy).or_default()
+            .record_success();
+    }
+
+    /// Tells the pool that a request through `proxy` failed, e.g. timed out or was answered
+    /// with 403. Enough failures in a row bench the proxy for the policy's cooldown.
+    pub fn report_failure(&mut self, proxy: &ProxyEndpoint, kind: FailureKind) {
+        if kind == FailureKind::Unsupported {
+            return;
+        }
+
+        let now = SystemTime::now();
+        self.health.entry(proxy.without_scheme()).or_default()
+            .record_failure(now, kind);
+
+        let breaker = self.breakers.entry(proxy.without_scheme()).or_default();
+        breaker.record_failure(now, &self.breaker_policy);
 
         #[cfg(feature = "logging")]
-        log::debug!("test: {:?}", test);
-        return Ok(test);
+        if !breaker.is_closed() {
+            log::debug!("benched proxy {} after {} failures", proxy, breaker.consecutive_failures);
+        }
+    }
+
+    /// Whether `proxy` is kept out of selection by its circuit breaker.
+    pub fn is_benched(&self, proxy: &ProxyEndpoint) -> bool {
+        return self.breakers.get(&proxy.without_scheme()).is_some_and(|breaker| !breaker.is_closed());
+    }
+
+    pub fn breaker(&self, proxy: &ProxyEndpoint) -> Option<&CircuitBreaker> {
+        return self.breakers.get(&proxy.without_scheme());
+    }
+
+    /// Benched pool members whose cooldown is over, one test per endpoint. Their breakers
+    /// move to half-open, and the result of probing them goes to `report_probe`.
+    pub fn take_probes(&mut self, now: SystemTime) -> Vec<ProxyTest> {
+        let mut probes: Vec<ProxyTest> = Vec::new();
+        for test in &self.proxies {
+            if probes.iter().any(|probe| probe.proxy == test.proxy) {
+                continue;
+            }
+
+            if self.breakers.get_mut(&test.proxy).is_some_and(|breaker| breaker.take_probe(now)) {
+                probes.push(test.clone());
+            }
+        }
+
+        return probes;
+    }
+
+    /// Records the result of probing a benched proxy, re-admitting it on success.
+    pub fn report_probe(&mut self, result: Result<ProxyTest, ProxyFailure>) {
+        match result {
+            Ok(test) => {
+                self.report_success(&test.proxy, test.rtt);
+                self.merge_test(test);
+            },
+            Err(failure) => self.report_failure(&failure.proxy, failure.kind),
+        }
+    }
+
+    /// Adds `test` to the pool, replacing the existing test of the same endpoint and protocol
+    /// if `test` is newer. A newer test also closes the endpoint's breaker, so members that pass
+    /// revalidation return to selection without waiting for a probe. Returns whether the pool
+    /// changed.
+    pub fn merge_test(&mut self, mut test: ProxyTest) -> bool {
+        test.proxy = test.proxy.without_scheme();
+        self.untested.retain(|proxy| proxy.scheme.as_ref().is_some_and(|scheme| scheme != &test.protocol) || !same_address(proxy, &test.proxy));
+        let existing = self.proxies.iter_mut()
+            .find(|known| known.proxy == test.proxy && known.protocol == test.protocol);
+
+        match existing {
+            Some(known) if known.time >= test.time => return false,
+            Some(known) => *known = test.clone(),
+            None => self.proxies.push(test.clone()),
+        }
+
+        if let Some(breaker) = self.breakers.get_mut(&test.proxy) {
+            breaker.record_success();
+        }
+
+        self.health.entry(test.proxy).or_default()
+            .record_success(test.time, test.rtt);
+        return true;
+    }
+
+    pub fn tests(&self) -> &[ProxyTest] {
+        return &self.proxies;
+    }
+
+    /// Whether `proxy` is a pool member, tested or not.
+    pub fn contains(&self, proxy: &ProxyEndpoint) -> bool {
+        return self.untested.contains(proxy) || self.proxies.iter().any(|test| same_address(&test.proxy, proxy));
+    }
+
+    pub fn len(&self) -> usize {
+        return self.proxies.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.proxies.is_empty();
+    }
+
+    /// Writes the pool to `path`, untested endpoints included. Each endpoint's failures in a
+    /// row and breaker state are saved with it, the rest of its health history is not.
+    pub fn save(&self, path: &Path, format: StoreFormat) -> Result<(), BoxError> {
+        let records = self.proxies.iter()
+            .map(|test| ProxyRecord::from(test).with_health(self.health(&test.proxy), self.breaker(&test.proxy)))
+            .chain(self.untested.iter().map(|proxy| ProxyRecord::untested(proxy).with_health(self.health(proxy), self.breaker(proxy))))
+            .collect::<Vec<_>>();
+
+        return store::write_records(path, format, &records);
+    }
+
+    /// Merges the pool saved at `path` into this one, keeping the most recent test of each
+    /// endpoint and protocol along with the failure streaks saved with it. Returns how many
+    /// tests and untested endpoints were added or replaced.
+    pub fn load(&mut self, path: &Path, format: StoreFormat) -> Result<usize, BoxError> {
+        let mut changed = 0;
+        for record in store::read_records(path, format)? {
+            let proxy = ProxyEndpoint::from_str(&record.proxy)?;
+            let merged = match record.untested {
+                true => self.import_untested(proxy.clone()),
+                false => self.merge_test(ProxyTest::try_from(record.clone())?),
+            };
+
+            if merged {
+                self.restore_health(&proxy, &record);
+                changed += 1;
+            }
+        }
+
+        #[cfg(feature = "logging")]
+        log::info!("loaded {} proxy tests from {}", changed, path.display());
+        return Ok(changed);
+    }
+
+    /// Puts back the failure streaks `save` wrote with `record`.
+    fn restore_health(&mut self, proxy: &ProxyEndpoint, record: &ProxyRecord) {
+        let proxy = proxy.without_scheme();
+        if record.failures > 0 {
+            let health = self.health.entry(proxy.clone()).or_default();
+            health.consecutive_failures = record.failures;
+            health.last_failure = health.last_failure.max(Some(UNIX_EPOCH + Duration::from_millis(record.failed_at)));
+        }
+
+        if record.breaker_failures > 0 || record.benched_until > 0 {
+            let breaker = self.breakers.entry(proxy).or_default();
+            breaker.consecutive_failures = record.breaker_failures;
+            if record.benched_until > 0 {
+                breaker.state = BreakerState::Open { until: UNIX_EPOCH + Duration::from_millis(record.benched_until) };
+            }
+        }
+    }
+
+    /// Health of a pool member in `0.0..=1.0` from its endpoint's test history.
+    pub fn score(&self, test: &ProxyTest) -> f64 {
+        return match self.health.get(&test.proxy) {
+            Some(health) => health.score(SystemTime::now()),
+            None => 0.0,
+        };
+    }
+
+    /// Pool members, healthiest first. Ties are broken by the latest round trip.
+    pub fn sorted(&self) -> Vec<&ProxyTest> {
+        let mut tests = self.proxies.iter()
+            .map(|test| (self.score(test), test))
+            .collect::<Vec<_>>();
+
+        tests.sort_by(|(a_score, a), (b_score, b)| b_score.total_cmp(a_score).then_with(|| a.cmp(b)));
+        return tests.into_iter().map(|(_, test)| test).collect();
+    }
+
+    /// Removes endpoints whose score fell below `min_score`, returning them.
+    pub fn evict(&mut self, min_score: f64) -> Vec<ProxyEndpoint> {
+        let now = SystemTime::now();
+        let evicted = self.health.iter()
+            .filter(|(_, health)| health.score(now) < min_score)
+            .map(|(proxy, _)| proxy.clone())
+            .collect::<Vec<_>>();
+
+        self.remove(&evicted);
+        return evicted;
     }
 
-    pub async fn test_proxies(proxies: &Vec<(Ipv4Addr, u16)>) -> Result<Vec<ProxyTest>, Box<dyn Error + Send + Sync>> {
+    /// Drops every record of `proxies` from the pool, whatever scheme they are given with.
+    pub fn remove(&mut self, proxies: &[ProxyEndpoint]) {
+        let removed = |proxy: &ProxyEndpoint| proxies.iter().any(|other| same_address(proxy, other));
+        self.proxies.retain(|test| !removed(&test.proxy));
+        self.untested.retain(|proxy| !removed(proxy));
+        self.last_used.retain(|(proxy, _), _| !removed(proxy));
+        for proxy in proxies {
+            self.health.remove(&proxy.without_scheme());
+            self.breakers.remove(&proxy.without_scheme());
+
+            #[cfg(feature = "logging")]
+            log::debug!("evicted proxy {}", proxy);
+        }
+    }
+
+    /// Picks a pool member matching `filter` with `strategy` and marks it as used. Benched
+    /// proxies are skipped. `key` is passed on to strategies with affinity, such as `Sticky`.
+    pub fn select(&mut self, strategy: &mut dyn SelectionStrategy, filter: &ProxyFilter, key: Option<&str>) -> Option<ProxyTest> {
+        let now = SystemTime::now();
+        let candidates = self.proxies.iter()
+            .filter(|test| filter.matches(test, now) && !self.is_benched(&test.proxy))
+            .map(|test| Candidate {
+                test,
+                score: self.score(test),
+                last_used: self.last_used.get(&(test.proxy.clone(), test.protocol.clone())).copied()
+            })
+            .collect::<Vec<_>>();
+
+        let selected = candidates.get(strategy.select(&candidates, key)?)?.test.clone();
+        self.last_used.insert((selected.proxy.clone(), selected.protocol.clone()), now);
+
         #[cfg(feature = "logging")]
-        log::info!("testing {} proxies: {:?}", proxies.len(), proxies);
-        let proxied_ips: Arc<Mutex<Vec<ProxyTest>>> = Arc::new(Mutex::new(Vec::new()));
-
-        const CONCURRENCY: usize = 20;
-        for i in (0..proxies.len()).filter(|x| x % CONCURRENCY == 0) {
-            log::debug!("[ProxyManager::test_proxies] {}", i);
-            async_scoped::TokioScope::scope_and_block(|s| {
-                for j in i..min(i + CONCURRENCY, proxies.len()) {
-                    let proxy = proxies[j];
-                    let proxied_ips_ref = &proxied_ips;
-
-                    #[cfg(feature = "logging")]
-                    log::debug!("[ProxyManager::test_proxies] Spawned task {}", j);
-                    s.spawn(async move {
-                        tokio::time::sleep(Duration::from_millis((j * 10) as u64)).await;
-                        let socks_test= match ProxyManager::test_proxy(&SupportedProtocols::Socks5, &proxy).await {
-                            Ok(test) => Some(test),
-                            Err(_) => None
-                        };
-
-                        let http_test = match ProxyManager::test_proxy(&SupportedProtocols::Http, &proxy).await {
-                            Ok(test) => Some(test),
-                            Err(_) => None
-                        };
-
-                        {
-                            if socks_test.is_some() {
-                                proxied_ips_ref.lock().await.push(socks_test.unwrap());
-
-                                #[cfg(feature = "logging")]
-                                log::debug!("success socks5 proxy {:?}", proxy);
-                            }
-
-                            if http_test.is_some() {
-                                proxied_ips_ref.lock().await.push(http_test.unwrap());
-
-                                #[cfg(feature = "logging")]
-                                log::debug!("success http proxy {:?}", proxy);
-                            }
-                        }
-                    });
-                }
+        log::debug!("selected {} proxy {}", selected.protocol, selected.proxy);
+        return Some(selected);
+    }
+
+    pub fn select_proxy(&mut self, strategy: &mut dyn SelectionStrategy, filter: &ProxyFilter, key: Option<&str>) -> Result<reqwest::Proxy, BoxError> {
+        let test = self.select(strategy, filter, key)
+            .ok_or(obfstr::obfstr!("no proxy matches the filter"))?;
+
+        return test.proxy.to_reqwest_proxy(&test.protocol);
+    }
+
+    /// A client that sends every request through the selected proxy.
+    pub fn select_client(&mut self, strategy: &mut dyn SelectionStrategy, filter: &ProxyFilter, key: Option<&str>) -> Result<reqwest::Client, BoxError> {
+        let proxy = self.select_proxy(strategy, filter, key)?;
+        return Ok(reqwest::Client::builder()
+            .proxy(proxy)
+            .user_agent(random_user_agent())
+            .build()?);
+    }
+
+    /// Working proxies as `scheme://[user:pass@]host:port` URLs, healthiest first.
+    pub fn export_urls(&self) -> Vec<String> {
+        return self.sorted().iter()
+            .map(|test| test.proxy.to_url(&test.protocol))
+            .collect();
+    }
+
+    /// The pool members matching `filter` in `format`, healthiest first.
+    pub fn export(&self, format: ExportFormat, filter: &ProxyFilter) -> Result<String, BoxError> {
+        let now = SystemTime::now();
+        let tests = self.sorted().into_iter()
+            .filter(|test| filter.matches(test, now))
+            .collect::<Vec<_>>();
+
+        return export::export(&tests, format);
+    }
+
+    pub fn export_to(&self, path: &Path, format: ExportFormat, filter: &ProxyFilter) -> Result<(), BoxError> {
+        std::fs::write(path, self.export(format, filter)?)?;
+        return Ok(());
+    }
+
+    pub async fn test_proxy(protocol: &SupportedProtocols, proxy: &ProxyEndpoint) -> Result<ProxyTest, Box<dyn Error + Send + Sync>> {
+        return ProxyTester::default().test_proxy(protocol, proxy).await;
+    }
+
+    pub async fn test_proxies(proxies: &[ProxyEndpoint]) -> Result<ProxyTestOutcome, Box<dyn Error + Send + Sync>> {
+        return ProxyTester::default().test_proxies(proxies).await;
+    }
+
+    /// Working proxies as they are found, tested with the default settings.
+    pub fn test_proxies_stream(proxies: Vec<ProxyEndpoint>) -> BoxStream<'static, ProxyTest> {
+        return ProxyTester::default().test_proxies_stream(proxies);
+    }
+
+    /// Tests proxies against `validators` instead of the default IP echo check.
+    pub async fn test_proxies_with(proxies: &[ProxyEndpoint], validators: Vec<Arc<dyn ProxyValidator>>) -> Result<ProxyTestOutcome, Box<dyn Error + Send + Sync>> {
+        return ProxyTester::default().with_validators(validators).test_proxies(proxies).await;
+    }
+}
+
+/// Whether `a` and `b` are the same endpoint, whatever scheme they were published with.
+fn same_address(a: &ProxyEndpoint, b: &ProxyEndpoint) -> bool {
+    return a.host == b.host && a.port == b.port && a.credentials == b.credentials;
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn test_at(proxy: &str, millis: u64) -> ProxyTest {
+        return ProxyTest {
+            proxy: ProxyEndpoint::from_str(proxy).unwrap(),
+            protocol: SupportedProtocols::Http,
+            status: StatusCode::OK,
+            text: "203.0.113.7".to_string(),
+            time: UNIX_EPOCH + Duration::from_millis(millis),
+            rtt: Duration::from_millis(millis % 1000),
+            anonymity: None
+        };
+    }
+
+    #[test]
+    fn test_load_merges() -> Result<(), BoxError> {
+        let mut saved = ProxyManager::new();
+        saved.import_test(test_at("10.0.0.1:8080", 2_000));
+        saved.import_test(test_at("10.0.0.2:8080", 1_000));
+
+        let path = store::temp_path("pool.jsonl");
+        saved.save(&path, StoreFormat::JsonLines)?;
+
+        // 10.0.0.1 is older on disk, 10.0.0.2 newer, 10.0.0.3 only in memory.
+        let mut mgr = ProxyManager::new();
+        mgr.import_test(test_at("10.0.0.1:8080", 3_000));
+        mgr.import_test(test_at("10.0.0.2:8080", 500));
+        mgr.import_test(test_at("10.0.0.3:8080", 100));
+        assert_eq!(mgr.load(&path, StoreFormat::JsonLines)?, 1);
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(mgr.len(), 3);
+        assert_eq!(mgr.tests()[0], test_at("10.0.0.1:8080", 3_000));
+        assert_eq!(mgr.tests()[1], test_at("10.0.0.2:8080", 1_000));
+        return Ok(());
+    }
+
+    #[test]
+    fn test_scheme_variants_share_one_entry() -> Result<(), BoxError> {
+        let mut mgr = ProxyManager::new();
+        assert!(mgr.merge_test(test_at("10.0.0.1:1080", 1_000)));
+        assert!(mgr.merge_test(ProxyTest { protocol: SupportedProtocols::Socks5, ..test_at("socks5://10.0.0.1:1080", 2_000) }));
+        assert!(mgr.merge_test(test_at("http://10.0.0.1:1080", 3_000)));
+        assert_eq!(mgr.len(), 2);
+        assert!(mgr.tests().iter().all(|test| test.proxy.scheme.is_none()));
+
+        let proxy = ProxyEndpoint::from_str("socks5://10.0.0.1:1080")?;
+        mgr.report_failure(&proxy, FailureKind::Timeout);
+        assert_eq!(mgr.health(&ProxyEndpoint::from_str("10.0.0.1:1080")?).map(|health| health.consecutive_failures), Some(1));
+
+        mgr.remove(&[proxy]);
+        assert!(mgr.is_empty());
+        assert!(mgr.health(&ProxyEndpoint::from_str("10.0.0.1:1080")?).is_none());
+        return Ok(());
+    }
+
+    #[test]
+    fn test_health_drives_sorting_and_eviction() -> Result<(), BoxError> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+        let mut mgr = ProxyManager::new();
+        mgr.import_test(test_at("10.0.0.1:8080", now - now % 1000 + 50));
+        mgr.import_test(test_at("10.0.0.2:8080", now - now % 1000 + 900));
+
+        // The faster proxy has failed ever since its one good test.
+        let flaky = ProxyEndpoint::from_str("10.0.0.1:8080")?;
+        for _ in 0..10 {
+            mgr.record_failure(&ProxyFailure {
+                proxy: flaky.clone(),
+                protocol: Some(SupportedProtocols::Http),
+                kind: FailureKind::Timeout,
+                message: String::new(),
+                time: SystemTime::now()
             });
         }
 
-        let mut tests = Arc::try_unwrap(proxied_ips).unwrap().into_inner();
-        tests.sort();
-        return Ok(tests);
+        assert_eq!(mgr.sorted()[0].proxy, ProxyEndpoint::from_str("10.0.0.2:8080")?);
+        assert_eq!(mgr.evict(0.2), [flaky]);
+        assert_eq!(mgr.len(), 1);
+        return Ok(());
+    }
+
+    #[test]
+    fn test_reported_failures_bench() -> Result<(), BoxError> {
+        let mut mgr = ProxyManager::new().with_breaker_policy(BreakerPolicy {
+            failure_threshold: 2,
+            cooldown: Duration::from_secs(60)
+        });
+        mgr.import_test(test_at("10.0.0.1:8080", 100));
+        mgr.import_test(test_at("10.0.0.2:8080", 900));
+
+        let fast = ProxyEndpoint::from_str("10.0.0.1:8080")?;
+        let mut strategy = crate::select::LowestRtt;
+        mgr.report_failure(&fast, FailureKind::BadStatus);
+        assert_eq!(mgr.select(&mut strategy, &ProxyFilter::new(), None).map(|test| test.proxy), Some(fast.clone()));
+
+        mgr.report_failure(&fast, FailureKind::Timeout);
+        assert!(mgr.is_benched(&fast));
+        assert_ne!(mgr.select(&mut strategy, &ProxyFilter::new(), None).map(|test| test.proxy), Some(fast.clone()));
+
+        assert!(mgr.take_probes(SystemTime::now()).is_empty());
+        let probes = mgr.take_probes(SystemTime::now() + Duration::from_secs(60));
+        assert_eq!(probes.len(), 1);
+        assert!(mgr.is_benched(&fast));
+
+        let mut probe = probes[0].clone();
+        probe.time = SystemTime::now();
+        mgr.report_probe(Ok(probe));
+        assert!(!mgr.is_benched(&fast));
+
+        // A fresh test, as the revalidator merges them, closes the breaker as well.
+        mgr.report_failure(&fast, FailureKind::Timeout);
+        mgr.report_failure(&fast, FailureKind::Timeout);
+        assert!(mgr.is_benched(&fast));
+        assert!(mgr.merge_test(ProxyTest { time: SystemTime::now() + Duration::from_secs(1), ..test_at("10.0.0.1:8080", 100) }));
+        assert!(!mgr.is_benched(&fast));
+        assert_eq!(mgr.breaker(&fast).map(|breaker| breaker.consecutive_failures), Some(0));
+        return Ok(());
+    }
+
+    #[test]
+    fn test_failure_streaks_survive_reload() -> Result<(), BoxError> {
+        let mut mgr = ProxyManager::new();
+        mgr.import_test(test_at("10.0.0.1:8080", 100));
+        let failing = ProxyEndpoint::from_str("10.0.0.1:8080")?;
+        for _ in 0..3 {
+            mgr.report_failure(&failing, FailureKind::Timeout);
+        }
+        assert!(mgr.is_benched(&failing));
+
+        let path = store::temp_path("streaks.jsonl");
+        mgr.save(&path, StoreFormat::JsonLines)?;
+        let mut loaded = ProxyManager::new();
+        assert_eq!(loaded.load(&path, StoreFormat::JsonLines)?, 1);
+        std::fs::remove_file(&path)?;
+
+        assert!(loaded.is_benched(&failing));
+        let until = match mgr.breaker(&failing).map(|breaker| breaker.state) {
+            Some(BreakerState::Open { until }) => truncate_millis(until),
+            state => panic!("breaker not open: {:?}", state),
+        };
+        assert_eq!(loaded.breaker(&failing), Some(&CircuitBreaker { state: BreakerState::Open { until }, consecutive_failures: 3 }));
+        let health = loaded.health(&failing).map(|health| (health.consecutive_failures, health.last_failure));
+        assert_eq!(health, mgr.health(&failing).map(|health| (health.consecutive_failures, health.last_failure.map(truncate_millis))));
+        return Ok(());
+    }
+
+    fn truncate_millis(time: SystemTime) -> SystemTime {
+        return UNIX_EPOCH + Duration::from_millis(time.duration_since(UNIX_EPOCH).unwrap().as_millis() as u64);
+    }
+
+    #[test]
+    fn test_untested_imports_are_kept_apart() -> Result<(), BoxError> {
+        let mut mgr = ProxyManager::new();
+        let proxy = ProxyEndpoint::from_str("socks5://10.0.0.3:1080")?;
+        assert!(mgr.import_untested(proxy.clone()));
+        assert!(!mgr.import_untested(proxy.clone()));
+        assert!(mgr.tests().is_empty());
+        assert!(mgr.select(&mut crate::select::LowestRtt, &ProxyFilter::new(), None).is_none());
+        assert!(mgr.export_urls().is_empty());
+
+        let path = store::temp_path("untested.jsonl");
+        mgr.save(&path, StoreFormat::JsonLines)?;
+        let mut loaded = ProxyManager::new();
+        assert_eq!(loaded.load(&path, StoreFormat::JsonLines)?, 1);
+        std::fs::remove_file(&path)?;
+        assert_eq!(loaded.untested(), std::slice::from_ref(&proxy));
+
+        let mut tested = test_at("socks5://10.0.0.3:1080", 0);
+        tested.protocol = SupportedProtocols::Socks5;
+        tested.time = SystemTime::now();
+        assert!(mgr.merge_test(tested));
+        assert!(mgr.untested().is_empty());
+        assert_eq!(mgr.select(&mut crate::select::LowestRtt, &ProxyFilter::new(), None).map(|test| test.proxy), Some(proxy.without_scheme()));
+
+        // Untested endpoints that keep failing are evicted like any other.
+        for _ in 0..10 {
+            loaded.record_failure(&ProxyFailure::no_protocol(&loaded.untested()[0].clone()));
+        }
+        assert_eq!(loaded.evict(0.2).len(), 1);
+        assert!(loaded.untested().is_empty());
+        return Ok(());
+    }
+
+    #[test]
+    fn test_untested_imports_without_scheme() -> Result<(), BoxError> {
+        let mut mgr = ProxyManager::new();
+        let proxy = ProxyEndpoint::from_str("10.0.0.4:1080")?;
+        assert!(mgr.import_untested(proxy.clone()));
+        assert!(!mgr.import_untested(proxy.clone()));
+
+        let path = store::temp_path("untested-bare.jsonl");
+        mgr.save(&path, StoreFormat::JsonLines)?;
+        let mut loaded = ProxyManager::new();
+        assert_eq!(loaded.load(&path, StoreFormat::JsonLines)?, 1);
+        std::fs::remove_file(&path)?;
+        assert_eq!(loaded.untested(), std::slice::from_ref(&proxy));
+
+        // Any protocol that works settles the endpoint, and a tested endpoint is not re-added bare.
+        let mut tested = test_at("10.0.0.4:1080", 0);
+        tested.protocol = SupportedProtocols::Socks4;
+        tested.time = SystemTime::now();
+        assert!(mgr.merge_test(tested));
+        assert!(mgr.untested().is_empty());
+        assert!(!mgr.import_untested(proxy));
+        return Ok(());
     }
-}
\ No newline at end of file
+}
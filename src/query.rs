@@ -0,0 +1,263 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use futures::stream::StreamExt;
+use serde::{Deserialize, Serialize};
+use crate::crawler::Crawler;
+use crate::endpoint::ProxyEndpoint;
+use crate::utility::BoxError;
+
+/// Phrasings proxy lists are commonly published under, in English, Russian, Portuguese,
+/// Spanish and Chinese.
+pub const DEFAULT_TERMS: [&str; 14] = [
+    "free proxy list",
+    "socks5 list",
+    "proxy list txt",
+    "http proxies updated",
+    "socks4 proxy list",
+    "elite proxy list",
+    "список прокси",
+    "бесплатные прокси socks5",
+    "lista de proxy grátis",
+    "proxies gratuitos atualizados",
+    "lista de proxies gratis",
+    "免费代理列表",
+    "免费代理IP",
+    "代理IP 每日更新",
+];
+
+/// A search term with what its runs turned up.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueryTerm {
+    pub text: String,
+    #[serde(default)]
+    pub runs: u32,
+    /// Distinct proxies this term's pages yielded, whether or not another term found them too,
+    /// so that the statistics do not depend on the order the terms ran in.
+    #[serde(default)]
+    pub proxies: usize,
+    /// Pages that yielded at least one proxy.
+    #[serde(default)]
+    pub productive_sources: usize,
+    /// Milliseconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_run: Option<u64>,
+    /// Searches in a row that failed, reset by the next run that gets results.
+    #[serde(default)]
+    pub failures: u32,
+}
+
+impl QueryTerm {
+    pub fn new(text: &str) -> QueryTerm {
+        return QueryTerm {
+            text: text.to_string(),
+            runs: 0,
+            proxies: 0,
+            productive_sources: 0,
+            last_run: None,
+            failures: 0
+        }
+    }
+
+    /// Productive sources per run, then proxies per run. `None` before the first run.
+    pub fn yield_per_run(&self) -> Option<(f64, f64)> {
+        if self.runs == 0 {
+            return None;
+        }
+
+        let runs = self.runs as f64;
+        return Some((self.productive_sources as f64 / runs, self.proxies as f64 / runs));
+    }
+
+    pub fn last_run_time(&self) -> Option<SystemTime> {
+        return self.last_run.map(|millis| UNIX_EPOCH + Duration::from_millis(millis));
+    }
+}
+
+/// Search terms a crawl runs through, with per-term statistics so that the terms that led to
+/// productive sources are searched first next time. Stored as a JSON array of `QueryTerm`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct QuerySet {
+    terms: Vec<QueryTerm>,
+}
+
+impl QuerySet {
+    pub fn new() -> QuerySet {
+        return QuerySet::default();
+    }
+
+    /// A set of `DEFAULT_TERMS`.
+    pub fn default_terms() -> QuerySet {
+        return DEFAULT_TERMS.iter().fold(QuerySet::new(), |set, term| set.with_term(term));
+    }
+
+    /// Adds `text` unless the set already has it.
+    pub fn with_term(mut self, text: &str) -> QuerySet {
+        if !self.terms.iter().any(|term| term.text == text) {
+            self.terms.push(QueryTerm::new(text));
+        }
+
+        return self;
+    }
+
+    pub fn load(path: &Path) -> Result<QuerySet, BoxError> {
+        let terms: Vec<QueryTerm> = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        return Ok(QuerySet { terms });
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), BoxError> {
+        std::fs::write(path, serde_json::to_string_pretty(&self.terms)? + "\n")?;
+        return Ok(());
+    }
+
+    pub fn terms(&self) -> &[QueryTerm] {
+        return &self.terms;
+    }
+
+    pub fn get(&self, text: &str) -> Option<&QueryTerm> {
+        return self.terms.iter().find(|term| term.text == text);
+    }
+
+    /// Terms in the order they should be searched: terms whose last search failed go last,
+    /// fewest failures in a row first. The others start with the terms that never ran, so each
+    /// gets tried, followed by productive sources and proxies per run. Ties keep the configured
+    /// order.
+    pub fn prioritized(&self) -> Vec<&QueryTerm> {
+        let mut terms = self.terms.iter().collect::<Vec<_>>();
+        terms.sort_by(|a, b| a.failures.cmp(&b.failures).then_with(|| match (a.yield_per_run(), b.yield_per_run()) {
+            (None, None) => std::cmp::Ordering::Equal,
+            (None, Some(_)) => std::cmp::Ordering::Less,
+            (Some(_), None) => std::cmp::Ordering::Greater,
+            (Some((a_sources, a_proxies)), Some((b_sources, b_proxies))) => b_sources.total_cmp(&a_sources)
+                .then_with(|| b_proxies.total_cmp(&a_proxies)),
+        }));
+
+        return terms;
+    }
+
+    /// Adds a run of `text` that found `proxies` proxies on `productive_sources` pages.
+    pub fn record(&mut self, text: &str, productive_sources: usize, proxies: usize, time: SystemTime) {
+        if let Some(term) = self.terms.iter_mut().find(|term| term.text == text) {
+            term.runs += 1;
+            term.proxies += proxies;
+            term.productive_sources += productive_sources;
+            term.last_run = Some(time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64);
+            term.failures = 0;
+        }
+    }
+
+    /// Adds a failed search for `text`, which moves it behind the terms that can be searched.
+    pub fn record_failure(&mut self, text: &str) {
+        if let Some(term) = self.terms.iter_mut().find(|term| term.text == text) {
+            term.failures += 1;
+        }
+    }
+
+    /// Crawls the terms in `prioritized` order until `limit` distinct proxies were found,
+    /// recording each term's run. `limit` is only checked between terms, so every recorded run
+    /// scraped all of its term's results; a term whose search fails only has the failure recorded.
+    pub async fn crawl<C>(&mut self, crawler: &C, limit: usize) -> Vec<ProxyEndpoint>
+        where C: Crawler + Sync {
+        let terms = self.prioritized().into_iter().map(|term| term.text.clone()).collect::<Vec<_>>();
+        let mut seen = HashSet::new();
+        let mut proxies = Vec::new();
+        for term in terms {
+            if proxies.len() >= limit {
+                break;
+            }
+
+            let urls = match crawler.search(&term).await {
+                Ok(urls) => urls,
+                Err(_e) => {
+                    #[cfg(feature = "logging")]
+                    log::warn!("search for query {:?} failed: {:?}", term, _e);
+                    self.record_failure(&term);
+                    continue;
+                }
+            };
+
+            let found = crawler.scrape_stream(urls, usize::MAX).collect::<Vec<_>>().await;
+            let sources = found.iter().map(|(source, _)| source).collect::<HashSet<_>>().len();
+
+            #[cfg(feature = "logging")]
+            log::debug!("query {:?} found {} proxies on {} sources", term, found.len(), sources);
+            self.record(&term, sources, found.len(), SystemTime::now());
+
+            for (_, proxy) in found {
+                if proxies.len() < limit && seen.insert(proxy.clone()) {
+                    proxies.push(proxy);
+                }
+            }
+        }
+
+        return proxies;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use std::net::Ipv4Addr;
+    use reqwest::Url;
+    use crate::store::temp_path;
+
+    // "socks5 list" finds two pages of proxies, "proxy list txt" one, and searching for
+    // "socks list mirror" fails.
+    struct TermCrawler;
+
+    #[async_trait::async_trait]
+    impl Crawler for TermCrawler {
+        async fn search(&self, text: &str) -> Result<Vec<Url>, BoxError> {
+            if text == "socks list mirror" {
+                return Err("rate limited".into());
+            }
+
+            let pages: &[u8] = match text {
+                "socks5 list" => &[2, 3],
+                "proxy list txt" => &[1],
+                _ => &[],
+            };
+
+            return Ok(pages.iter().map(|page| Url::from_str(&format!("http://lists.example.com/{}", page)).unwrap()).collect());
+        }
+
+        async fn scrape_proxies(&self, url: &Url) -> Result<Vec<ProxyEndpoint>, BoxError> {
+            let page = url.path()[1..].parse::<u8>()?;
+            return Ok((0..5).map(|i| ProxyEndpoint::from((Ipv4Addr::new(10, 0, page, i), 1080))).collect());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_set_prioritizes_productive_terms() -> Result<(), BoxError> {
+        let mut queries = QuerySet::new()
+            .with_term("free proxy list")
+            .with_term("proxy list txt")
+            .with_term("socks5 list")
+            .with_term("socks list mirror");
+
+        assert_eq!(queries.crawl(&TermCrawler, 100).await.len(), 15);
+        assert_eq!(queries.get("proxy list txt").map(|term| (term.runs, term.proxies)), Some((1, 5)));
+        assert_eq!(queries.get("socks5 list").map(|term| term.productive_sources), Some(2));
+
+        // The failed search does not count as a run, but moves its term to the back.
+        assert_eq!(queries.get("socks list mirror").map(|term| (term.runs, term.failures)), Some((0, 1)));
+        let order = queries.prioritized().iter().map(|term| term.text.as_str()).collect::<Vec<_>>();
+        assert_eq!(order, ["socks5 list", "proxy list txt", "free proxy list", "socks list mirror"]);
+
+        // The next run reaches its limit on the best term alone, which is still recorded in full.
+        assert_eq!(queries.crawl(&TermCrawler, 3).await.len(), 3);
+        assert_eq!(queries.terms().iter().map(|term| term.runs).collect::<Vec<_>>(), [1, 1, 2, 0]);
+        assert_eq!(queries.get("socks5 list").map(|term| term.proxies), Some(20));
+
+        // A run without a limit still gets to the failing term and counts its failure again.
+        queries.crawl(&TermCrawler, 100).await;
+        assert_eq!(queries.get("socks list mirror").map(|term| (term.runs, term.failures)), Some((0, 2)));
+
+        let path = temp_path("queries.json");
+        queries.save(&path)?;
+        assert_eq!(QuerySet::load(&path)?, queries);
+        std::fs::remove_file(&path)?;
+        return Ok(());
+    }
+}
@@ -0,0 +1,407 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use futures::stream::{self, StreamExt};
+use tokio::sync::{watch, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use crate::endpoint::ProxyEndpoint;
+use crate::outcome::ProxyFailure;
+use crate::proxy::{ProxyManager, ProxyTest};
+use crate::tester::ProxyTester;
+
+/// How often the pool is checked for members that are not scheduled yet.
+const RESCAN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Run state shared between a `Revalidator` task and its handle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RevalidatorState {
+    Running,
+    Paused,
+    Stopped,
+}
+
+/// Retests pool members on a schedule, updating their records and evicting endpoints that
+/// keep failing. Benched members are probed once their cooldown is over.
+#[derive(Debug, Clone)]
+pub struct Revalidator {
+    pub tester: ProxyTester,
+    /// Time between tests of an endpoint whose last test succeeded.
+    pub interval: Duration,
+    /// Time between tests of an endpoint whose last test failed.
+    pub unhealthy_interval: Duration,
+    /// Failed tests in a row after which an endpoint is removed from the pool.
+    pub max_consecutive_failures: u32,
+}
+
+impl Default for Revalidator {
+    fn default() -> Self {
+        return Revalidator {
+            tester: ProxyTester::default(),
+            interval: Duration::from_secs(10 * 60),
+            unhealthy_interval: Duration::from_secs(60),
+            max_consecutive_failures: 3
+        }
+    }
+}
+
+/// An endpoint waiting for its next test, ordered by due time only.
+#[derive(Debug, Clone)]
+struct Due {
+    at: Instant,
+    proxy: ProxyEndpoint,
+}
+
+impl PartialEq for Due {
+    fn eq(&self, other: &Self) -> bool {
+        return self.at == other.at;
+    }
+}
+
+impl Eq for Due {}
+
+impl PartialOrd for Due {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl Ord for Due {
+    fn cmp(&self, other: &Self) -> Ordering {
+        return self.at.cmp(&other.at);
+    }
+}
+
+/// Controls a spawned `Revalidator`. Dropping the handle stops the task.
+#[derive(Debug)]
+pub struct RevalidatorHandle {
+    control: watch::Sender<RevalidatorState>,
+    task: JoinHandle<()>,
+}
+
+impl RevalidatorHandle {
+    /// Stops starting new tests until `resume`. Tests already running finish and are recorded.
+    pub fn pause(&self) {
+        self.control.send_replace(RevalidatorState::Paused);
+    }
+
+    pub fn resume(&self) {
+        self.control.send_replace(RevalidatorState::Running);
+    }
+
+    pub fn state(&self) -> RevalidatorState {
+        return *self.control.borrow();
+    }
+
+    /// Stops the task, waiting for the tests in flight to be recorded.
+    pub async fn stop(self) {
+        self.control.send_replace(RevalidatorState::Stopped);
+        let _ = self.task.await;
+    }
+}
+
+impl Revalidator {
+    pub fn new() -> Revalidator {
+        return Revalidator::default();
+    }
+
+    pub fn with_tester(mut self, tester: ProxyTester) -> Revalidator {
+        self.tester = tester;
+        return self;
+    }
+
+    pub fn with_intervals(mut self, interval: Duration, unhealthy_interval: Duration) -> Revalidator {
+        self.interval = interval;
+        self.unhealthy_interval = unhealthy_interval;
+        return self;
+    }
+
+    pub fn with_max_consecutive_failures(mut self, failures: u32) -> Revalidator {
+        self.max_consecutive_failures = failures;
+        return self;
+    }
+
+    /// Starts retesting the members of `pool` in the background.
+    pub fn spawn(self, pool: Arc<Mutex<ProxyManager>>) -> RevalidatorHandle {
+        let (control, state) = watch::channel(RevalidatorState::Running);
+        let task = tokio::spawn(self.run(pool, state));
+        return RevalidatorHandle { control, task };
+    }
+
+    fn next_interval(&self, consecutive_failures: u32) -> Duration {
+        return match consecutive_failures {
+            0 => self.interval,
+            _ => self.unhealthy_interval,
+        };
+    }
+
+    async fn run(self, pool: Arc<Mutex<ProxyManager>>, mut state: watch::Receiver<RevalidatorState>) {
+        let mut queue = BinaryHeap::new();
+        let mut scheduled = HashSet::new();
+        loop {
+            let current = *state.borrow_and_update();
+            match current {
+                RevalidatorState::Stopped => return,
+                RevalidatorState::Paused => {
+                    if state.changed().await.is_err() {
+                        return;
+                    }
+                    continue;
+                },
+                RevalidatorState::Running => {}
+            }
+
+            self.schedule_new(&pool, &mut queue, &mut scheduled).await;
+            self.probe_benched(&pool).await;
+
+            let now = Instant::now();
+            let mut due = Vec::new();
+            while queue.peek().is_some_and(|Reverse(next): &Reverse<Due>| next.at <= now) {
+                if let Some(Reverse(next)) = queue.pop() {
+                    due.push(next.proxy);
+                }
+            }
+
+            if !due.is_empty() {
+                self.revalidate(&pool, due, &mut queue, &mut scheduled).await;
+                continue;
+            }
+
+            let wake = match queue.peek() {
+                Some(Reverse(next)) => next.at.min(now + RESCAN_INTERVAL),
+                None => now + RESCAN_INTERVAL,
+            };
+
+            tokio::select! {
+                _ = tokio::time::sleep_until(wake) => {},
+                changed = state.changed() => {
+                    if changed.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Queues pool members that are not scheduled yet, due one interval after their last test.
+    /// Untested endpoints are due right away.
+    async fn schedule_new(&self, pool: &Mutex<ProxyManager>, queue: &mut BinaryHeap<Reverse<Due>>, scheduled: &mut HashSet<ProxyEndpoint>) {
+        let pool = pool.lock().await;
+        let now = SystemTime::now();
+        for proxy in pool.untested() {
+            if scheduled.insert(proxy.clone()) {
+                queue.push(Reverse(Due { at: Instant::now(), proxy: proxy.clone() }));
+            }
+        }
+
+        for test in pool.tests() {
+            if scheduled.contains(&test.proxy) {
+                continue;
+            }
+
+            let failures = pool.health(&test.proxy).map_or(0, |health| health.consecutive_failures);
+            let due = test.time + self.next_interval(failures);
+            let at = Instant::now() + due.duration_since(now).unwrap_or_default();
+            scheduled.insert(test.proxy.clone());
+            queue.push(Reverse(Due { at, proxy: test.proxy.clone() }));
+        }
+    }
+
+    /// Probes benched members whose cooldown is over, without holding the pool during the tests.
+    async fn probe_benched(&self, pool: &Mutex<ProxyManager>) {
+        let probes = pool.lock().await.take_probes(SystemTime::now());
+        if probes.is_empty() {
+            return;
+        }
+
+        let results = stream::iter(probes)
+            .map(|probe| async move {
+                return self.tester.try_proxy(&probe.protocol, &probe.proxy).await;
+            })
+            .buffer_unordered(self.tester.concurrency.max(1))
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut pool = pool.lock().await;
+        for result in results {
+            pool.report_probe(result);
+        }
+    }
+
+    /// Retests every protocol the pool knows `due` endpoints by, untested ones under their
+    /// scheme or, without one, under every protocol their handshake answers to. Then records
+    /// the results and either reschedules or evicts each endpoint.
+    async fn revalidate(&self, pool: &Mutex<ProxyManager>, due: Vec<ProxyEndpoint>, queue: &mut BinaryHeap<Reverse<Due>>, scheduled: &mut HashSet<ProxyEndpoint>) {
+        let jobs = {
+            let pool = pool.lock().await;
+            due.into_iter()
+                .map(|proxy| {
+                    let mut protocols = pool.tests().iter()
+                        .filter(|test| test.proxy == proxy)
+                        .map(|test| test.protocol.clone())
+                        .collect::<Vec<_>>();
+                    let untested = pool.untested().contains(&proxy);
+                    if untested {
+                        protocols.extend(proxy.scheme.clone().filter(|scheme| !protocols.contains(scheme)));
+                    }
+                    let detect = untested && proxy.scheme.is_none();
+                    (proxy, protocols, detect)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let results = stream::iter(jobs)
+            .map(|(proxy, protocols, detect)| async move {
+                if detect {
+                    return (proxy.clone(), self.tester.test_endpoint(&proxy).await);
+                }
+
+                let mut results = Vec::with_capacity(protocols.len());
+                for protocol in &protocols {
+                    results.push(self.tester.try_proxy(protocol, &proxy).await);
+                }
+                return (proxy, results);
+            })
+            .buffer_unordered(self.tester.concurrency.max(1))
+            .collect::<Vec<(ProxyEndpoint, Vec<Result<ProxyTest, ProxyFailure>>)>>()
+            .await;
+
+        let mut pool = pool.lock().await;
+        for (proxy, results) in results {
+            scheduled.remove(&proxy);
+
+            // Members evicted or removed while the test ran stay removed.
+            if results.is_empty() || !pool.contains(&proxy) {
+                continue;
+            }
+
+            // Failures first, so that one working protocol keeps the endpoint healthy.
+            let (alive, dead): (Vec<_>, Vec<_>) = results.into_iter().partition(|result| result.is_ok());
+            for failure in dead.into_iter().filter_map(Result::err) {
+                pool.record_failure(&failure);
+            }
+            for test in alive.into_iter().filter_map(Result::ok) {
+                pool.merge_test(test);
+            }
+
+            let failures = pool.health(&proxy).map_or(0, |health| health.consecutive_failures);
+            if failures >= self.max_consecutive_failures {
+                #[cfg(feature = "logging")]
+                log::info!("evicting proxy {} after {} failed tests", proxy, failures);
+                pool.remove(std::slice::from_ref(&proxy));
+                continue;
+            }
+
+            let at = Instant::now() + self.next_interval(failures);
+            scheduled.insert(proxy.clone());
+            queue.push(Reverse(Due { at, proxy }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+    use crate::proxy::SupportedProtocols;
+    use crate::utility::BoxError;
+
+    #[tokio::test]
+    async fn test_evicts_dead_members() -> Result<(), BoxError> {
+        // Nothing listens on the port once the listener is dropped.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let dead = ProxyEndpoint::from(listener.local_addr()?);
+        drop(listener);
+
+        let mut mgr = ProxyManager::new();
+        mgr.import_test(ProxyTest {
+            proxy: dead.clone(),
+            protocol: SupportedProtocols::Http,
+            status: StatusCode::OK,
+            text: String::new(),
+            time: SystemTime::now() - Duration::from_secs(3600),
+            rtt: Duration::from_millis(100),
+            anonymity: None
+        });
+        let pool = Arc::new(Mutex::new(mgr));
+
+        let handle = Revalidator::new()
+            .with_tester(ProxyTester::new().without_judge())
+            .with_intervals(Duration::from_secs(3600), Duration::from_millis(10))
+            .with_max_consecutive_failures(2)
+            .spawn(pool.clone());
+
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while !pool.lock().await.is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }).await?;
+
+        assert!(pool.lock().await.health(&dead).is_none());
+        handle.pause();
+        assert_eq!(handle.state(), RevalidatorState::Paused);
+        handle.stop().await;
+        return Ok(());
+    }
+
+    #[tokio::test]
+    async fn test_members_removed_mid_test_stay_removed() -> Result<(), BoxError> {
+        // Connections are accepted by the kernel but never answered, so the test times out.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let silent = ProxyEndpoint::from(listener.local_addr()?);
+
+        let mut mgr = ProxyManager::new();
+        mgr.import_test(ProxyTest {
+            proxy: silent.clone(),
+            protocol: SupportedProtocols::Http,
+            status: StatusCode::OK,
+            text: String::new(),
+            time: SystemTime::now() - Duration::from_secs(3600),
+            rtt: Duration::from_millis(100),
+            anonymity: None
+        });
+        let pool = Mutex::new(mgr);
+
+        let mut tester = ProxyTester::new().without_judge();
+        tester.timeout = Duration::from_millis(500);
+        let revalidator = Revalidator::new().with_tester(tester);
+
+        let mut queue = BinaryHeap::new();
+        let mut scheduled = HashSet::new();
+        let removal = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            pool.lock().await.remove(std::slice::from_ref(&silent));
+        };
+        tokio::join!(revalidator.revalidate(&pool, vec![silent.clone()], &mut queue, &mut scheduled), removal);
+
+        let pool = pool.lock().await;
+        assert!(!pool.contains(&silent));
+        assert!(pool.health(&silent).is_none());
+        assert!(queue.is_empty() && scheduled.is_empty());
+        return Ok(());
+    }
+
+    #[tokio::test]
+    async fn test_untested_without_scheme_are_probed() -> Result<(), BoxError> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let dead = ProxyEndpoint::from(listener.local_addr()?);
+        drop(listener);
+
+        let mut mgr = ProxyManager::new();
+        assert!(mgr.import_untested(dead.clone()));
+        let pool = Mutex::new(mgr);
+
+        let revalidator = Revalidator::new().with_tester(ProxyTester::new().without_judge());
+        let mut queue = BinaryHeap::new();
+        let mut scheduled = HashSet::new();
+        revalidator.revalidate(&pool, vec![dead.clone()], &mut queue, &mut scheduled).await;
+
+        // Nothing answered the handshake probes, which counts as a failed test.
+        let pool = pool.lock().await;
+        assert_eq!(pool.health(&dead).map(|health| health.consecutive_failures), Some(1));
+        assert_eq!(pool.untested(), std::slice::from_ref(&dead));
+        assert!(scheduled.contains(&dead));
+        return Ok(());
+    }
+}
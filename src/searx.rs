@@ -0,0 +1,120 @@
+use reqwest::Url;
+use std::str::FromStr;
+use std::time::Duration;
+use std::error::Error;
+use crate::crawler::{get_text, SearchEngine, SearchResult};
+
+/// The instance's `/search` endpoint, under `base_url`'s path whether or not it ends in a slash.
+fn search_url(base_url: &Url) -> Result<Url, Box<dyn Error + Send + Sync>> {
+    let mut url = base_url.clone();
+    url.set_query(None);
+    url.path_segments_mut()
+        .map_err(|_| format!("not a searxng base url: {}", base_url))?
+        .pop_if_empty()
+        .push(obfstr::obfstr!("search"));
+    return Ok(url);
+}
+
+fn parse_result_urls(text: &str) -> Result<Vec<Url>, Box<dyn Error + Send + Sync>> {
+    let response: serde_json::Value = serde_json::from_str(text)?;
+    let results = response.get("results")
+        .and_then(|results| results.as_array())
+        .ok_or(obfstr::obfstr!("searxng response without results"))?;
+
+    let mut urls: Vec<Url> = Vec::new();
+    for result in results {
+        let url = match result.get("url").and_then(|url| url.as_str()).map(Url::from_str) {
+            Some(Ok(url)) => url,
+            _ => continue,
+        };
+
+        if url.domain().is_some() && !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+
+    return Ok(urls);
+}
+
+/// A SearXNG instance's JSON API, which has to be enabled under `search.formats` in the
+/// instance settings. Pointing it at a local instance avoids public instances' rate limits.
+pub struct SearxNg {
+    web: reqwest::Client,
+    /// Instance root, such as `http://127.0.0.1:8888/` or `https://example.org/searxng`.
+    pub base_url: Url,
+    pub timeout: Duration
+}
+
+impl SearxNg {
+    pub fn new(web: reqwest::Client, base_url: Url) -> SearxNg {
+        return SearxNg {
+            web,
+            base_url,
+            timeout: Duration::from_secs(30)
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> SearxNg {
+        self.timeout = timeout;
+        return self;
+    }
+}
+
+#[async_trait::async_trait]
+impl SearchEngine for SearxNg {
+    async fn search_n(&self, text: &str, page: u16) -> Result<SearchResult, Box<dyn Error + Send + Sync>> {
+        let mut url = search_url(&self.base_url)?;
+        url.query_pairs_mut()
+            .append_pair("q", text)
+            .append_pair("format", "json")
+            .append_pair("pageno", &(u32::from(page) + 1).to_string());
+
+        let text = get_text(&self.web, url, self.timeout).await?;
+
+        // The API does not say whether there are more pages; an empty one ends the results.
+        let urls = parse_result_urls(&text)?;
+        let has_next = !urls.is_empty();
+        return Ok(SearchResult { urls, page, has_next });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_result_urls() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let json = r#"{
+  "query": "free proxy list",
+  "number_of_results": 0,
+  "results": [
+    {"url": "https://proxies.example.com/", "title": "Free Proxy List", "engine": "bing", "engines": ["bing", "mojeek"], "score": 4.0},
+    {"url": "https://raw.example.org/http.txt", "title": "http.txt", "engine": "google", "score": 1.5},
+    {"url": "https://proxies.example.com/", "title": "Free Proxy List", "engine": "duckduckgo", "score": 1.0},
+    {"title": "No link"}
+  ],
+  "answers": [],
+  "suggestions": ["free proxy server"],
+  "unresponsive_engines": []
+}"#;
+
+        assert_eq!(parse_result_urls(json)?, [
+            Url::from_str("https://proxies.example.com/")?,
+            Url::from_str("https://raw.example.org/http.txt")?
+        ]);
+        assert!(parse_result_urls(r#"{"results": []}"#)?.is_empty());
+        assert!(parse_result_urls("<html>Too Many Requests</html>").is_err());
+        return Ok(());
+    }
+
+    #[test]
+    fn test_search_url() -> Result<(), Box<dyn Error + Send + Sync>> {
+        for base in ["http://127.0.0.1:8888", "http://127.0.0.1:8888/"] {
+            assert_eq!(search_url(&Url::from_str(base)?)?.as_str(), "http://127.0.0.1:8888/search");
+        }
+        for base in ["https://example.org/searxng", "https://example.org/searxng/"] {
+            assert_eq!(search_url(&Url::from_str(base)?)?.as_str(), "https://example.org/searxng/search");
+        }
+        return Ok(());
+    }
+}
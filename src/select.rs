@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+use rand::distributions::{Distribution, WeightedIndex};
+use crate::anonymity::AnonymityLevel;
+use crate::endpoint::ProxyEndpoint;
+use crate::proxy::{ProxyTest, SupportedProtocols};
+
+/// Restricts which pool members selection may return. Unset fields do not filter.
+#[derive(Debug, Clone, Default)]
+pub struct ProxyFilter {
+    /// Accepted protocols, any when empty.
+    pub protocols: Vec<SupportedProtocols>,
+    /// Protocols never returned, even when listed in `protocols`.
+    pub excluded_protocols: Vec<SupportedProtocols>,
+    pub max_rtt: Option<Duration>,
+    pub min_anonymity: Option<AnonymityLevel>,
+    /// Maximum time since the proxy was last tested.
+    pub max_age: Option<Duration>,
+    /// Endpoints never returned, e.g. ones a request already failed through.
+    pub excluded: Vec<ProxyEndpoint>,
+}
+
+impl ProxyFilter {
+    pub fn new() -> ProxyFilter {
+        return ProxyFilter::default();
+    }
+
+    pub fn protocol(mut self, protocol: SupportedProtocols) -> ProxyFilter {
+        self.protocols.push(protocol);
+        return self;
+    }
+
+    pub fn exclude_protocol(mut self, protocol: SupportedProtocols) -> ProxyFilter {
+        self.excluded_protocols.push(protocol);
+        return self;
+    }
+
+    pub fn max_rtt(mut self, rtt: Duration) -> ProxyFilter {
+        self.max_rtt = Some(rtt);
+        return self;
+    }
+
+    pub fn min_anonymity(mut self, level: AnonymityLevel) -> ProxyFilter {
+        self.min_anonymity = Some(level);
+        return self;
+    }
+
+    pub fn max_age(mut self, age: Duration) -> ProxyFilter {
+        self.max_age = Some(age);
+        return self;
+    }
+
+    pub fn exclude(mut self, proxy: ProxyEndpoint) -> ProxyFilter {
+        self.excluded.push(proxy);
+        return self;
+    }
+
+    pub fn matches(&self, test: &ProxyTest, now: SystemTime) -> bool {
+        if self.excluded.contains(&test.proxy) {
+            return false;
+        }
+
+        if !self.protocols.is_empty() && !self.protocols.contains(&test.protocol) {
+            return false;
+        }
+
+        if self.excluded_protocols.contains(&test.protocol) {
+            return false;
+        }
+
+        if let Some(max_rtt) = self.max_rtt {
+            if test.rtt > max_rtt {
+                return false;
+            }
+        }
+
+        if let Some(min_anonymity) = self.min_anonymity {
+            // Unclassified proxies are not trusted to meet any anonymity requirement.
+            if test.anonymity.is_none_or(|level| level < min_anonymity) {
+                return false;
+            }
+        }
+
+        if let Some(max_age) = self.max_age {
+            let age = now.duration_since(test.time).unwrap_or_default();
+            if age > max_age {
+                return false;
+            }
+        }
+
+        return true;
+    }
+}
+
+/// A pool member that passed the filter, as seen by a `SelectionStrategy`.
+#[derive(Debug, Clone)]
+pub struct Candidate<'a> {
+    pub test: &'a ProxyTest,
+    /// Health in `0.0..=1.0`, higher is better.
+    pub score: f64,
+    pub last_used: Option<SystemTime>,
+}
+
+/// Picks one of the candidates, returning its index. `key` identifies the caller's target
+/// for strategies that keep affinity, e.g. the domain being requested.
+pub trait SelectionStrategy: Send {
+    fn select(&mut self, candidates: &[Candidate], key: Option<&str>) -> Option<usize>;
+}
+
+/// Cycles through the candidates in pool order.
+#[derive(Debug, Clone, Default)]
+pub struct RoundRobin {
+    next: usize,
+}
+
+impl SelectionStrategy for RoundRobin {
+    fn select(&mut self, candidates: &[Candidate], _key: Option<&str>) -> Option<usize> {
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let index = self.next % candidates.len();
+        self.next = index + 1;
+        return Some(index);
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LowestRtt;
+
+impl SelectionStrategy for LowestRtt {
+    fn select(&mut self, candidates: &[Candidate], _key: Option<&str>) -> Option<usize> {
+        return candidates.iter().enumerate()
+            .min_by_key(|(_, candidate)| candidate.test.rtt)
+            .map(|(index, _)| index);
+    }
+}
+
+/// Random choice where each candidate's chance is proportional to its score.
+#[derive(Debug, Clone, Default)]
+pub struct WeightedRandom;
+
+impl SelectionStrategy for WeightedRandom {
+    fn select(&mut self, candidates: &[Candidate], _key: Option<&str>) -> Option<usize> {
+        // A small floor keeps zero-score candidates selectable when nothing else is left.
+        let weights = candidates.iter().map(|candidate| candidate.score.max(0.0) + 0.001);
+        return match WeightedIndex::new(weights) {
+            Ok(distribution) => Some(distribution.sample(&mut rand::thread_rng())),
+            Err(_) => None,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LeastRecentlyUsed;
+
+impl SelectionStrategy for LeastRecentlyUsed {
+    fn select(&mut self, candidates: &[Candidate], _key: Option<&str>) -> Option<usize> {
+        // `None` sorts first, so never-used proxies are preferred.
+        return candidates.iter().enumerate()
+            .min_by_key(|(_, candidate)| candidate.last_used)
+            .map(|(index, _)| index);
+    }
+}
+
+/// Returns the same proxy for the same key for as long as it stays a candidate, delegating
+/// to `fallback` for new keys, keyless selections and proxies that dropped out.
+pub struct Sticky {
+    fallback: Box<dyn SelectionStrategy>,
+    assignments: HashMap<String, (ProxyEndpoint, SupportedProtocols)>,
+}
+
+impl Sticky {
+    pub fn new(fallback: Box<dyn SelectionStrategy>) -> Sticky {
+        return Sticky {
+            fallback,
+            assignments: HashMap::new()
+        }
+    }
+
+    pub fn forget(&mut self, key: &str) {
+        self.assignments.remove(key);
+    }
+}
+
+impl Default for Sticky {
+    fn default() -> Self {
+        return Sticky::new(Box::new(LowestRtt));
+    }
+}
+
+impl SelectionStrategy for Sticky {
+    fn select(&mut self, candidates: &[Candidate], key: Option<&str>) -> Option<usize> {
+        let key = match key {
+            Some(key) => key,
+            None => return self.fallback.select(candidates, None),
+        };
+
+        if let Some((proxy, protocol)) = self.assignments.get(key) {
+            let assigned = candidates.iter()
+                .position(|candidate| &candidate.test.proxy == proxy && &candidate.test.protocol == protocol);
+            if assigned.is_some() {
+                return assigned;
+            }
+        }
+
+        let index = self.fallback.select(candidates, Some(key))?;
+        let test = candidates[index].test;
+        self.assignments.insert(key.to_string(), (test.proxy.clone(), test.protocol.clone()));
+        return Some(index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use std::time::UNIX_EPOCH;
+    use reqwest::StatusCode;
+
+    fn test_with_rtt(proxy: &str, rtt_ms: u64) -> ProxyTest {
+        return ProxyTest {
+            proxy: ProxyEndpoint::from_str(proxy).unwrap(),
+            protocol: SupportedProtocols::Http,
+            status: StatusCode::OK,
+            text: String::new(),
+            time: UNIX_EPOCH,
+            rtt: Duration::from_millis(rtt_ms),
+            anonymity: Some(AnonymityLevel::Anonymous)
+        };
+    }
+
+    #[test]
+    fn test_strategies() {
+        let tests = [test_with_rtt("10.0.0.1:80", 300), test_with_rtt("10.0.0.2:80", 100), test_with_rtt("10.0.0.3:80", 200)];
+        let candidates = tests.iter()
+            .map(|test| Candidate { test, score: 1.0, last_used: Some(UNIX_EPOCH + test.rtt) })
+            .collect::<Vec<_>>();
+
+        let mut round_robin = RoundRobin::default();
+        let picks = (0..4).map(|_| round_robin.select(&candidates, None).unwrap()).collect::<Vec<_>>();
+        assert_eq!(picks, [0, 1, 2, 0]);
+
+        assert_eq!(LowestRtt.select(&candidates, None), Some(1));
+        assert_eq!(LeastRecentlyUsed.select(&candidates, None), Some(1));
+
+        let mut sticky = Sticky::new(Box::new(RoundRobin::default()));
+        assert_eq!(sticky.select(&candidates, Some("example.com")), Some(0));
+        assert_eq!(sticky.select(&candidates, Some("example.org")), Some(1));
+        assert_eq!(sticky.select(&candidates, Some("example.com")), Some(0));
+        assert_eq!(sticky.select(&candidates[1..], Some("example.com")), Some(0));
+
+        let filter = ProxyFilter::new()
+            .max_rtt(Duration::from_millis(250))
+            .min_anonymity(AnonymityLevel::Elite);
+        assert!(!filter.matches(&tests[1], UNIX_EPOCH));
+        assert!(ProxyFilter::new().max_rtt(Duration::from_millis(250)).matches(&tests[1], UNIX_EPOCH));
+        assert!(!ProxyFilter::new().exclude(tests[1].proxy.clone()).matches(&tests[1], UNIX_EPOCH));
+    }
+}
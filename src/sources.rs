@@ -0,0 +1,266 @@
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use crate::endpoint::ProxyEndpoint;
+use crate::import::{self, ImportFormat};
+use crate::utility::BoxError;
+use crate::crawler::{self, Fetcher, HttpFetcher, ProxyExtractor};
+use crate::Crawler;
+
+/// How proxies are pulled out of a fetched source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceExtractor {
+    /// `host:port` pairs anywhere in the page plus HTML table rows, as for search results.
+    Html,
+    Plain,
+    Csv,
+    Json,
+    Proxychains,
+}
+
+impl Default for SourceExtractor {
+    fn default() -> Self {
+        return SourceExtractor::Html;
+    }
+}
+
+impl ProxyExtractor for SourceExtractor {
+    fn extract(&self, _url: &Url, text: &str) -> Result<Vec<ProxyEndpoint>, BoxError> {
+        return match self {
+            SourceExtractor::Html => crawler::parse_proxy_pairs(text),
+            SourceExtractor::Plain => import::parse(text, ImportFormat::Plain),
+            SourceExtractor::Csv => import::parse(text, ImportFormat::Csv),
+            SourceExtractor::Json => import::parse(text, ImportFormat::Json),
+            SourceExtractor::Proxychains => import::parse(text, ImportFormat::Proxychains),
+        };
+    }
+}
+
+/// `url` as `Url` serialises it, e.g. with a lowercase host and a `/` path, so that a source is
+/// found again under the URL it was fetched as. Unparsable URLs are kept as they are.
+fn normalize_source_url(url: &str) -> String {
+    return Url::from_str(url).map(String::from).unwrap_or_else(|_| url.to_string());
+}
+
+fn default_refresh_secs() -> u64 {
+    return 3600;
+}
+
+/// A known proxy list. The URL is normalised when the source is added to a registry. The last two fields are filled in by `SourceRegistry::record_fetch`
+/// and saved along with the rest of the registry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProxySource {
+    pub url: String,
+    /// Seconds to wait after a fetch before the source is fetched again.
+    #[serde(default = "default_refresh_secs")]
+    pub refresh_secs: u64,
+    #[serde(default)]
+    pub extractor: SourceExtractor,
+    /// Milliseconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_fetch: Option<u64>,
+    /// Proxies extracted by the last fetch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_yield: Option<usize>,
+}
+
+impl ProxySource {
+    pub fn new(url: &str, refresh: Duration, extractor: SourceExtractor) -> ProxySource {
+        return ProxySource {
+            url: normalize_source_url(url),
+            refresh_secs: refresh.as_secs(),
+            extractor,
+            last_fetch: None,
+            last_yield: None
+        }
+    }
+
+    pub fn refresh(&self) -> Duration {
+        return Duration::from_secs(self.refresh_secs);
+    }
+
+    pub fn last_fetch_time(&self) -> Option<SystemTime> {
+        return self.last_fetch.map(|millis| UNIX_EPOCH + Duration::from_millis(millis));
+    }
+
+    /// Whether the source was never fetched or its refresh interval has passed.
+    pub fn is_due(&self, now: SystemTime) -> bool {
+        return match self.last_fetch_time() {
+            Some(last_fetch) => last_fetch + self.refresh() <= now,
+            None => true,
+        };
+    }
+}
+
+/// Proxy list URLs fetched directly instead of being found through a search engine. Stored as
+/// a JSON array of `ProxySource`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SourceRegistry {
+    sources: Vec<ProxySource>,
+}
+
+impl SourceRegistry {
+    pub fn new() -> SourceRegistry {
+        return SourceRegistry::default();
+    }
+
+    /// Adds `source`, replacing a source with the same URL.
+    pub fn with_source(mut self, mut source: ProxySource) -> SourceRegistry {
+        source.url = normalize_source_url(&source.url);
+        self.sources.retain(|known| known.url != source.url);
+        self.sources.push(source);
+        return self;
+    }
+
+    pub fn load(path: &Path) -> Result<SourceRegistry, BoxError> {
+        let mut sources: Vec<ProxySource> = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        for source in &mut sources {
+            source.url = Url::from_str(&source.url)?.into();
+        }
+
+        return Ok(SourceRegistry { sources });
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), BoxError> {
+        std::fs::write(path, serde_json::to_string_pretty(&self.sources)? + "\n")?;
+        return Ok(());
+    }
+
+    pub fn sources(&self) -> &[ProxySource] {
+        return &self.sources;
+    }
+
+    pub fn get(&self, url: &str) -> Option<&ProxySource> {
+        let url = normalize_source_url(url);
+        return self.sources.iter().find(|source| source.url == url);
+    }
+
+    /// Sources due at `now`, those that yielded the most proxies last time first.
+    pub fn due(&self, now: SystemTime) -> Vec<&ProxySource> {
+        let mut due = self.sources.iter()
+            .filter(|source| source.is_due(now))
+            .collect::<Vec<_>>();
+
+        due.sort_by_key(|source| std::cmp::Reverse(source.last_yield));
+        return due;
+    }
+
+    /// Remembers a successful fetch of `url` that extracted `found` proxies.
+    pub fn record_fetch(&mut self, url: &str, time: SystemTime, found: usize) {
+        let url = normalize_source_url(url);
+        if let Some(source) = self.sources.iter_mut().find(|source| source.url == url) {
+            source.last_fetch = Some(time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64);
+            source.last_yield = Some(found);
+        }
+    }
+}
+
+/// Crawls the sources of a `SourceRegistry` instead of searching. `search` ignores its text and
+/// returns the sources that are due, so `crawl` and `crawl_stream` fetch those.
+pub struct SourceCrawler {
+    fetcher: Arc<dyn Fetcher>,
+    registry: std::sync::Mutex<SourceRegistry>,
+}
+
+impl SourceCrawler {
+    pub fn new(builder: reqwest::ClientBuilder, registry: SourceRegistry) -> Result<SourceCrawler, BoxError> {
+        return Ok(SourceCrawler {
+            fetcher: Arc::new(HttpFetcher::new(builder.build()?)),
+            registry: std::sync::Mutex::new(registry),
+        });
+    }
+
+    pub fn with_fetcher(mut self, fetcher: Arc<dyn Fetcher>) -> SourceCrawler {
+        self.fetcher = fetcher;
+        return self;
+    }
+
+    /// The registry with the fetches made so far, e.g. to save it after crawling.
+    pub fn registry(&self) -> SourceRegistry {
+        return self.registry.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+    }
+}
+
+#[async_trait::async_trait]
+impl Crawler for SourceCrawler {
+    async fn search(&self, _text: &str) -> Result<Vec<Url>, BoxError> {
+        let registry = self.registry.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        return Ok(registry.due(SystemTime::now()).into_iter()
+            .filter_map(|source| Url::from_str(&source.url).ok())
+            .collect());
+    }
+
+    async fn scrape_proxies(&self, url: &Url) -> Result<Vec<ProxyEndpoint>, BoxError> {
+        let extractor = {
+            let registry = self.registry.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            registry.get(url.as_str()).map(|source| source.extractor).unwrap_or_default()
+        };
+
+        let proxies = extractor.extract(url, &self.fetcher.fetch(url).await?)?;
+        self.registry.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+            .record_fetch(url.as_str(), SystemTime::now(), proxies.len());
+
+        #[cfg(feature = "logging")]
+        log::debug!("source {} yielded {} proxies", url, proxies.len());
+        return Ok(proxies);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::temp_path;
+    use crate::tunnel;
+
+    #[tokio::test]
+    async fn test_source_crawler_remembers_fetches() -> Result<(), BoxError> {
+        let plain = tunnel::spawn_http_server("10.0.0.1:8080\n10.0.0.2:8080\n").await?;
+        let json = tunnel::spawn_http_server(r#"[{"ip": "10.0.0.3", "port": 1080, "protocol": "socks5"}]"#).await?;
+
+        let registry = SourceRegistry::new()
+            .with_source(ProxySource::new(&format!("http://{}/list.txt", plain), Duration::from_secs(600), SourceExtractor::Plain))
+            .with_source(ProxySource::new(&format!("http://{}/list.json", json), Duration::from_secs(600), SourceExtractor::Json));
+        let path = temp_path("sources.json");
+        registry.save(&path)?;
+
+        let crawler = SourceCrawler::new(reqwest::Client::builder().no_proxy(), SourceRegistry::load(&path)?)?;
+        let mut proxies = crawler.crawl("", 100).await?;
+        proxies.sort_by_key(|proxy| proxy.to_string());
+        assert_eq!(proxies.iter().map(|proxy| proxy.to_string()).collect::<Vec<_>>(), [
+            "10.0.0.1:8080", "10.0.0.2:8080", "socks5://10.0.0.3:1080"
+        ]);
+
+        // Both sources were just fetched, so nothing is due until their refresh passes.
+        let registry = crawler.registry();
+        assert_eq!(registry.sources().iter().map(|source| source.last_yield).collect::<Vec<_>>(), [Some(2), Some(1)]);
+        assert!(crawler.crawl("", 100).await?.is_empty());
+        assert_eq!(registry.due(SystemTime::now() + Duration::from_secs(600)).len(), 2);
+
+        registry.save(&path)?;
+        assert_eq!(SourceRegistry::load(&path)?, registry);
+        std::fs::remove_file(&path)?;
+        return Ok(());
+    }
+
+    #[tokio::test]
+    async fn test_source_urls_are_normalized() -> Result<(), BoxError> {
+        let json = tunnel::spawn_http_server(r#"[{"ip": "10.0.0.3", "port": 1080, "protocol": "socks5"}]"#).await?;
+        let url = format!("HTTP://{}", json);
+        let path = temp_path("sources.json");
+        std::fs::write(&path, format!(r#"[{{"url": "{}", "extractor": "json"}}]"#, url))?;
+        let registry = SourceRegistry::load(&path)?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(registry.sources()[0].url, format!("http://{}/", json));
+        assert!(registry.get(&url).is_some());
+
+        // Without the registry's extractor the JSON list would yield nothing.
+        let crawler = SourceCrawler::new(reqwest::Client::builder().no_proxy(), registry)?;
+        assert_eq!(crawler.crawl("", 100).await?.len(), 1);
+        assert_eq!(crawler.registry().sources()[0].last_yield, Some(1));
+        return Ok(());
+    }
+}
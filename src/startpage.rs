@@ -0,0 +1,115 @@
+use reqwest::Url;
+use std::str::FromStr;
+use std::time::Duration;
+use regex::Regex;
+use std::error::Error;
+use crate::crawler::{get_text, html_attribute, SearchEngine, SearchResult};
+
+fn parse_result_urls(text: &str) -> Vec<Url> {
+    lazy_static::lazy_static! {
+        static ref ANCHOR_PATTERN: Regex = Regex::new(obfstr::obfstr!(r#"(?i)<a\b[^>]*>"#))
+            .expect(obfstr::obfstr!("anchor_pattern construction"));
+    }
+
+    let mut urls: Vec<Url> = Vec::new();
+    for anchor in ANCHOR_PATTERN.find_iter(text) {
+        let class = html_attribute(anchor.as_str(), "class").unwrap_or_default();
+        if !class.split_whitespace().any(|class| class == "result-link") {
+            continue;
+        }
+
+        let url = match html_attribute(anchor.as_str(), "href").and_then(|href| Url::from_str(&href).ok()) {
+            Some(url) => url,
+            None => continue,
+        };
+
+        let domain = url.domain().unwrap_or_default();
+        if !domain.is_empty() && !domain.ends_with(obfstr::obfstr!("startpage.com")) && !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+
+    return urls;
+}
+
+/// Startpage pages through forms whose hidden `page` field holds the one-based page number.
+fn has_next_page(text: &str, page: u16) -> bool {
+    lazy_static::lazy_static! {
+        static ref PAGE_INPUT_PATTERN: Regex = Regex::new(obfstr::obfstr!(r#"(?i)<input\b[^>]*\bname="page"[^>]*>"#))
+            .expect(obfstr::obfstr!("page_input_pattern construction"));
+    }
+
+    let next = (u32::from(page) + 2).to_string();
+    return PAGE_INPUT_PATTERN.find_iter(text)
+        .any(|input| html_attribute(input.as_str(), "value").as_deref() == Some(next.as_str()));
+}
+
+/// Startpage, which serves Google's results without tracking.
+pub struct Startpage {
+    web: reqwest::Client,
+    pub timeout: Duration
+}
+
+impl Startpage {
+    pub fn new(web: reqwest::Client) -> Startpage {
+        return Startpage {
+            web,
+            timeout: Duration::from_secs(30)
+        }
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Startpage {
+        self.timeout = timeout;
+        return self;
+    }
+}
+
+#[async_trait::async_trait]
+impl SearchEngine for Startpage {
+    async fn search_n(&self, text: &str, page: u16) -> Result<SearchResult, Box<dyn Error + Send + Sync>> {
+        let page_number = (u32::from(page) + 1).to_string();
+        let url = Url::parse_with_params(obfstr::obfstr!("https://www.startpage.com/sp/search"), &[("query", text), ("page", &page_number)])?;
+        let text = get_text(&self.web, url, self.timeout).await?;
+
+        return Ok(SearchResult {
+            urls: parse_result_urls(&text),
+            page,
+            has_next: has_next_page(&text, page)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_result_urls() -> Result<(), Box<dyn Error + Send + Sync>> {
+        let html = r#"
+<div class="w-gl">
+  <div class="result css-o7i03b">
+    <a class="result-title result-link css-1bggj8v" href="https://list.example.io/proxies" target="_blank" rel="noopener nofollow noreferrer">
+      <h2 class="wgl-title css-i3irj7">Free Proxy List</h2>
+    </a>
+    <p class="description css-1507v2l">Updated every minute.</p>
+  </div>
+  <div class="result css-o7i03b">
+    <a href='https://socks.example.org/?page=2&amp;country=DE' class='result-link'>SOCKS</a>
+  </div>
+  <a class="result-link" href="https://www.startpage.com/do/settings">Settings</a>
+</div>
+<form action="/sp/search" method="post" class="pagination__form">
+  <input type="hidden" name="query" value="free proxy list">
+  <input type="hidden" name="page" value="2">
+  <button type="submit" class="pagination__next-prev-button next">Next</button>
+</form>"#;
+
+        assert_eq!(parse_result_urls(html), [
+            Url::from_str("https://list.example.io/proxies")?,
+            Url::from_str("https://socks.example.org/?page=2&country=DE")?
+        ]);
+        assert!(has_next_page(html, 0));
+        assert!(!has_next_page(html, 1));
+        return Ok(());
+    }
+}
@@ -0,0 +1,355 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use crate::anonymity::AnonymityLevel;
+use crate::breaker::{BreakerState, CircuitBreaker};
+use crate::endpoint::ProxyEndpoint;
+use crate::health::ProxyHealth;
+use crate::proxy::{ProxyTest, SupportedProtocols};
+use crate::utility::BoxError;
+
+/// On-disk representation of a `ProxyTest`, or of an endpoint waiting for its first test.
+/// Flat so that it fits a CSV row or an SQLite table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProxyRecord {
+    /// Endpoint as parsed by `ProxyEndpoint::from_str`, including credentials.
+    pub proxy: String,
+    pub protocol: String,
+    pub status: u16,
+    pub text: String,
+    /// Milliseconds since the Unix epoch.
+    pub tested_at: u64,
+    pub rtt_ms: u64,
+    pub anonymity: Option<String>,
+    /// Set on endpoints imported without a test; the test fields are then empty.
+    #[serde(default)]
+    pub untested: bool,
+    /// Failed tests in a row of the endpoint, repeated on each of its records.
+    #[serde(default)]
+    pub failures: u32,
+    /// Milliseconds since the Unix epoch of the endpoint's last failed test, 0 if it never failed.
+    #[serde(default)]
+    pub failed_at: u64,
+    /// Failures in a row counted by the endpoint's circuit breaker.
+    #[serde(default)]
+    pub breaker_failures: u32,
+    /// Milliseconds since the Unix epoch until which the endpoint is benched, 0 while its
+    /// breaker is closed.
+    #[serde(default)]
+    pub benched_until: u64,
+}
+
+impl ProxyRecord {
+    /// The record of `proxy` added untested under its URL scheme.
+    pub fn untested(proxy: &ProxyEndpoint) -> ProxyRecord {
+        return ProxyRecord {
+            proxy: proxy.to_string(),
+            protocol: proxy.scheme.as_ref().map(ToString::to_string).unwrap_or_default(),
+            status: 0,
+            text: String::new(),
+            tested_at: 0,
+            rtt_ms: 0,
+            anonymity: None,
+            untested: true,
+            failures: 0,
+            failed_at: 0,
+            breaker_failures: 0,
+            benched_until: 0
+        }
+    }
+
+    /// Adds the endpoint's failure streaks, which are not part of its tests.
+    pub fn with_health(mut self, health: Option<&ProxyHealth>, breaker: Option<&CircuitBreaker>) -> ProxyRecord {
+        if let Some(health) = health {
+            self.failures = health.consecutive_failures;
+            self.failed_at = health.last_failure.map_or(0, unix_millis);
+        }
+
+        if let Some(breaker) = breaker {
+            self.breaker_failures = breaker.consecutive_failures;
+            self.benched_until = match breaker.state {
+                BreakerState::Closed => 0,
+                BreakerState::Open { until } => unix_millis(until).max(1),
+                // Still benched, and due for a probe as soon as it is loaded.
+                BreakerState::HalfOpen => 1,
+            };
+        }
+
+        return self;
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    return time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+}
+
+impl From<&ProxyTest> for ProxyRecord {
+    fn from(test: &ProxyTest) -> Self {
+        return ProxyRecord {
+            proxy: test.proxy.to_string(),
+            protocol: test.protocol.to_string(),
+            status: test.status.as_u16(),
+            text: test.text.clone(),
+            tested_at: unix_millis(test.time),
+            rtt_ms: test.rtt.as_millis() as u64,
+            anonymity: test.anonymity.map(|level| level.to_string()),
+            untested: false,
+            failures: 0,
+            failed_at: 0,
+            breaker_failures: 0,
+            benched_until: 0
+        }
+    }
+}
+
+impl TryFrom<ProxyRecord> for ProxyTest {
+    type Error = BoxError;
+
+    fn try_from(record: ProxyRecord) -> Result<Self, Self::Error> {
+        return Ok(ProxyTest {
+            proxy: ProxyEndpoint::from_str(&record.proxy)?,
+            protocol: SupportedProtocols::from_str(&record.protocol)?,
+            status: StatusCode::from_u16(record.status)?,
+            text: record.text,
+            time: UNIX_EPOCH + Duration::from_millis(record.tested_at),
+            rtt: Duration::from_millis(record.rtt_ms),
+            anonymity: match record.anonymity.as_deref() {
+                Some(level) if !level.is_empty() => Some(AnonymityLevel::from_str(level)?),
+                _ => None,
+            }
+        });
+    }
+}
+
+/// File formats the proxy pool can be saved in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreFormat {
+    /// One JSON `ProxyRecord` per line.
+    JsonLines,
+    Csv,
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+}
+
+impl StoreFormat {
+    /// Guesses the format from the file extension. A plain `.json` file would be expected to
+    /// hold a single JSON document, so only `.jsonl` and `.ndjson` name JSON Lines.
+    pub fn from_path(path: &Path) -> Option<StoreFormat> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        return match extension.as_str() {
+            "jsonl" | "ndjson" => Some(StoreFormat::JsonLines),
+            "csv" => Some(StoreFormat::Csv),
+            #[cfg(feature = "sqlite")]
+            "sqlite" | "sqlite3" | "db" => Some(StoreFormat::Sqlite),
+            _ => None,
+        };
+    }
+}
+
+impl FromStr for StoreFormat {
+    type Err = BoxError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "jsonl" | "ndjson" | "json-lines" => Ok(StoreFormat::JsonLines),
+            "csv" => Ok(StoreFormat::Csv),
+            #[cfg(feature = "sqlite")]
+            "sqlite" => Ok(StoreFormat::Sqlite),
+            _ => Err(format!("unknown store format: {}", s).into()),
+        }
+    }
+}
+
+pub fn write_records(path: &Path, format: StoreFormat, records: &[ProxyRecord]) -> Result<(), BoxError> {
+    match format {
+        StoreFormat::JsonLines => {
+            let mut writer = BufWriter::new(File::create(path)?);
+            for record in records {
+                serde_json::to_writer(&mut writer, record)?;
+                writer.write_all(b"\n")?;
+            }
+            writer.flush()?;
+        },
+        StoreFormat::Csv => {
+            let mut writer = csv::Writer::from_path(path)?;
+            for record in records {
+                writer.serialize(record)?;
+            }
+            writer.flush()?;
+        },
+        #[cfg(feature = "sqlite")]
+        StoreFormat::Sqlite => write_sqlite(path, records)?,
+    }
+
+    return Ok(());
+}
+
+pub fn read_records(path: &Path, format: StoreFormat) -> Result<Vec<ProxyRecord>, BoxError> {
+    let mut records = Vec::new();
+    match format {
+        StoreFormat::JsonLines => {
+            for line in BufReader::new(File::open(path)?).lines() {
+                let line = line?;
+                if !line.trim().is_empty() {
+                    records.push(serde_json::from_str(&line)?);
+                }
+            }
+        },
+        StoreFormat::Csv => {
+            for record in csv::Reader::from_path(path)?.deserialize() {
+                records.push(record?);
+            }
+        },
+        #[cfg(feature = "sqlite")]
+        StoreFormat::Sqlite => records = read_sqlite(path)?,
+    }
+
+    return Ok(records);
+}
+
+#[cfg(feature = "sqlite")]
+const SQLITE_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS proxies (
+    proxy TEXT NOT NULL,
+    protocol TEXT NOT NULL,
+    status INTEGER NOT NULL,
+    text TEXT NOT NULL,
+    tested_at INTEGER NOT NULL,
+    rtt_ms INTEGER NOT NULL,
+    anonymity TEXT,
+    untested INTEGER NOT NULL DEFAULT 0,
+    failures INTEGER NOT NULL DEFAULT 0,
+    failed_at INTEGER NOT NULL DEFAULT 0,
+    breaker_failures INTEGER NOT NULL DEFAULT 0,
+    benched_until INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (proxy, protocol)
+)";
+
+/// Opens the database at `path`, creating the `proxies` table or adding the columns older
+/// versions did not have.
+#[cfg(feature = "sqlite")]
+fn open_sqlite(path: &Path) -> Result<rusqlite::Connection, BoxError> {
+    let connection = rusqlite::Connection::open(path)?;
+    connection.execute(SQLITE_SCHEMA, [])?;
+    for column in ["untested", "failures", "failed_at", "breaker_failures", "benched_until"] {
+        if connection.prepare(&format!("SELECT {} FROM proxies LIMIT 0", column)).is_err() {
+            connection.execute(&format!("ALTER TABLE proxies ADD COLUMN {} INTEGER NOT NULL DEFAULT 0", column), [])?;
+        }
+    }
+
+    return Ok(connection);
+}
+
+/// Replaces the contents of the `proxies` table with `records`.
+#[cfg(feature = "sqlite")]
+fn write_sqlite(path: &Path, records: &[ProxyRecord]) -> Result<(), BoxError> {
+    let mut connection = open_sqlite(path)?;
+
+    let transaction = connection.transaction()?;
+    transaction.execute("DELETE FROM proxies", [])?;
+    {
+        let mut insert = transaction.prepare(
+            "INSERT OR REPLACE INTO proxies (proxy, protocol, status, text, tested_at, rtt_ms, anonymity, untested,
+                 failures, failed_at, breaker_failures, benched_until)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)"
+        )?;
+
+        for record in records {
+            insert.execute(rusqlite::params![
+                record.proxy, record.protocol, record.status, record.text,
+                record.tested_at as i64, record.rtt_ms as i64, record.anonymity, record.untested,
+                record.failures, record.failed_at as i64, record.breaker_failures, record.benched_until as i64
+            ])?;
+        }
+    }
+
+    transaction.commit()?;
+    return Ok(());
+}
+
+#[cfg(feature = "sqlite")]
+fn read_sqlite(path: &Path) -> Result<Vec<ProxyRecord>, BoxError> {
+    let connection = open_sqlite(path)?;
+    let mut select = connection.prepare(
+        "SELECT proxy, protocol, status, text, tested_at, rtt_ms, anonymity, untested,
+             failures, failed_at, breaker_failures, benched_until FROM proxies"
+    )?;
+
+    let rows = select.query_map([], |row| {
+        return Ok(ProxyRecord {
+            proxy: row.get(0)?,
+            protocol: row.get(1)?,
+            status: row.get(2)?,
+            text: row.get(3)?,
+            tested_at: row.get::<_, i64>(4)? as u64,
+            rtt_ms: row.get::<_, i64>(5)? as u64,
+            anonymity: row.get(6)?,
+            untested: row.get(7)?,
+            failures: row.get(8)?,
+            failed_at: row.get::<_, i64>(9)? as u64,
+            breaker_failures: row.get(10)?,
+            benched_until: row.get::<_, i64>(11)? as u64
+        });
+    })?;
+
+    let mut records = Vec::new();
+    for row in rows {
+        records.push(row?);
+    }
+
+    return Ok(records);
+}
+
+#[cfg(test)]
+pub(crate) fn temp_path(name: &str) -> std::path::PathBuf {
+    let unique = std::time::SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+    return std::env::temp_dir().join(format!("sockeye-{}-{}-{}", std::process::id(), unique, name));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_round_trip() -> Result<(), BoxError> {
+        let test = ProxyTest {
+            proxy: ProxyEndpoint::from_str("user:pass@[2001:db8::1]:1080")?,
+            protocol: SupportedProtocols::Socks5h,
+            status: StatusCode::OK,
+            text: "203.0.113.7, \"quoted\"\nline".to_string(),
+            time: UNIX_EPOCH + Duration::from_millis(1_600_000_000_123),
+            rtt: Duration::from_millis(250),
+            anonymity: Some(AnonymityLevel::Elite)
+        };
+
+        let formats = [
+            StoreFormat::JsonLines,
+            StoreFormat::Csv,
+            #[cfg(feature = "sqlite")]
+            StoreFormat::Sqlite,
+        ];
+
+        let mut health = ProxyHealth::new();
+        health.record_failure(UNIX_EPOCH + Duration::from_secs(60), crate::outcome::FailureKind::Timeout);
+        let untested = ProxyRecord::untested(&ProxyEndpoint::from_str("socks4://10.0.0.1:1080")?)
+            .with_health(Some(&health), Some(&CircuitBreaker { state: BreakerState::HalfOpen, consecutive_failures: 3 }));
+        for format in formats {
+            let path = temp_path("store");
+            write_records(&path, format, &[ProxyRecord::from(&test), untested.clone()])?;
+            let records = read_records(&path, format)?;
+            std::fs::remove_file(&path)?;
+
+            assert_eq!(records.len(), 2);
+            assert_eq!(ProxyTest::try_from(records[0].clone())?, test);
+            assert_eq!(records[1], untested);
+        }
+
+        assert_eq!(StoreFormat::from_path(Path::new("pool.ndjson")), Some(StoreFormat::JsonLines));
+        assert_eq!(StoreFormat::from_path(Path::new("pool.json")), None);
+        return Ok(());
+    }
+}
@@ -0,0 +1,336 @@
+use std::time::{Duration, SystemTime};
+use std::error::Error;
+use std::sync::Arc;
+use futures::stream::{self, BoxStream, StreamExt};
+use tokio::sync::{mpsc, OnceCell, Semaphore};
+use crate::anonymity::ProxyJudge;
+use crate::chain::{self, ChainForwarder, ProxyChain};
+use crate::endpoint::ProxyEndpoint;
+use crate::outcome::{ProxyFailure, ProxyTestOutcome};
+use crate::probe;
+use crate::proxy::{ProxyTest, SupportedProtocols};
+use crate::utility::BoxError;
+use crate::validator::{IpEchoValidator, ProxyValidator};
+
+/// Settings for testing proxies. `ProxyManager::test_proxy` and `ProxyManager::test_proxies`
+/// use the defaults.
+#[derive(Clone, Debug)]
+pub struct ProxyTester {
+    pub timeout: Duration,
+    pub probe_timeout: Duration,
+    /// Maximum number of endpoints tested at once.
+    pub concurrency: usize,
+    /// Checks a proxy must pass, in order. The first validator's response is recorded on the test.
+    pub validators: Vec<Arc<dyn ProxyValidator>>,
+    /// Judge used to classify anonymity, `None` skips classification.
+    pub judge: Option<ProxyJudge>,
+    /// Our own public address as the judge should not see it. Looked up by the first test
+    /// that needs it when left unset.
+    pub real_ip: Option<String>,
+    /// Proxies every probe and test goes through first, so that the tested proxies only
+    /// ever see the chain's exit address.
+    pub upstream: Option<ProxyChain>,
+    /// The looked up `real_ip`, shared with clones so that it is only looked up once.
+    looked_up_ip: Arc<OnceCell<Option<String>>>,
+    /// Serves `upstream` to every test, spawned by the first one.
+    forwarder: Arc<OnceCell<ChainForwarder>>,
+}
+
+impl Default for ProxyTester {
+    fn default() -> Self {
+        return ProxyTester {
+            timeout: Duration::from_secs(30),
+            probe_timeout: Duration::from_secs(10),
+            concurrency: 20,
+            validators: vec![Arc::new(IpEchoValidator::default())],
+            judge: Some(ProxyJudge::default()),
+            real_ip: None,
+            upstream: None,
+            looked_up_ip: Arc::new(OnceCell::new()),
+            forwarder: Arc::new(OnceCell::new())
+        }
+    }
+}
+
+impl ProxyTester {
+    pub fn new() -> ProxyTester {
+        return ProxyTester::default();
+    }
+
+    pub fn with_concurrency(mut self, concurrency: usize) -> ProxyTester {
+        self.concurrency = concurrency;
+        return self;
+    }
+
+    pub fn with_validators(mut self, validators: Vec<Arc<dyn ProxyValidator>>) -> ProxyTester {
+        self.validators = validators;
+        return self;
+    }
+
+    pub fn with_judge(mut self, judge: ProxyJudge) -> ProxyTester {
+        self.judge = Some(judge);
+        return self;
+    }
+
+    pub fn without_judge(mut self) -> ProxyTester {
+        self.judge = None;
+        return self;
+    }
+
+    pub fn with_upstream(mut self, upstream: ProxyChain) -> ProxyTester {
+        self.upstream = Some(upstream);
+        self.looked_up_ip = Arc::new(OnceCell::new());
+        self.forwarder = Arc::new(OnceCell::new());
+        return self;
+    }
+
+    /// The forwarder in front of `upstream`, `None` without one.
+    async fn forwarder(&self) -> Result<Option<&ChainForwarder>, BoxError> {
+        let upstream = match &self.upstream {
+            Some(upstream) => upstream,
+            None => return Ok(None),
+        };
+
+        return Ok(Some(self.forwarder.get_or_try_init(|| upstream.spawn_forwarder()).await?));
+    }
+
+    /// Whether `protocol` can be tested at all. Behind an upstream, HTTPS proxies would be a
+    /// chain hop, which tunnels do not speak.
+    fn can_test(&self, protocol: &SupportedProtocols) -> bool {
+        return self.upstream.is_none() || *protocol != SupportedProtocols::Https;
+    }
+
+    /// Behind an upstream, the address tested proxies could leak is the upstream's exit.
+    async fn lookup_public_ip(&self) -> Result<String, BoxError> {
+        let forwarder = match self.forwarder().await? {
+            Some(forwarder) => forwarder,
+            None => return Ok(crate::crawler::public_ip().await?.1),
+        };
+
+        let client = reqwest::Client::builder().proxy(forwarder.to_reqwest_proxy()?).build()?;
+        return Ok(crate::crawler::public_ip_from(&client).await?.1);
+    }
+
+    async fn resolve_real_ip(&self) -> Option<String> {
+        if self.real_ip.is_some() || self.judge.is_none() {
+            return self.real_ip.clone();
+        }
+
+        // A failed lookup is remembered too, rather than retried by every test.
+        let looked_up = self.looked_up_ip.get_or_init(|| async {
+            return match self.lookup_public_ip().await {
+                Ok(ip) => Some(ip.trim().to_string()),
+                Err(_e) => {
+                    #[cfg(feature = "logging")]
+                    log::warn!("unable to look up public ip, skipping anonymity checks: {:?}", _e);
+                    None
+                }
+            };
+        }).await;
+
+        return looked_up.clone();
+    }
+
+    pub async fn test_proxy(&self, protocol: &SupportedProtocols, proxy: &ProxyEndpoint) -> Result<ProxyTest, Box<dyn Error + Send + Sync>> {
+        // The route is kept alive for the whole test when going through an upstream.
+        let (reqwest_proxy, _route) = chain::reqwest_proxy_via(self.forwarder().await?, proxy, protocol)?;
+        let client = reqwest::Client::builder().proxy(reqwest_proxy).build()?;
+
+        let mut responses = Vec::with_capacity(self.validators.len());
+        for validator in &self.validators {
+            let response = tokio::time::timeout(self.timeout, validator.validate(&client)).await??;
+            responses.push(response);
+        }
+
+        let response = responses.into_iter().next()
+            .ok_or(obfstr::obfstr!("no validators configured"))?;
+
+        let anonymity = match (&self.judge, self.resolve_real_ip().await) {
+            (Some(judge), Some(real_ip)) => judge.judge(&client, &real_ip).await.ok(),
+            _ => None,
+        };
+
+        let test = ProxyTest{
+            proxy: proxy.without_scheme(),
+            protocol: protocol.clone(),
+            time: SystemTime::now(),
+            status: response.status,
+            text: response.text,
+            rtt: response.rtt,
+            anonymity
+        };
+
+        #[cfg(feature = "logging")]
+        log::debug!("test: {:?}", test);
+        return Ok(test);
+    }
+
+    /// Like `test_proxy`, but classifies the error. Protocols this tester cannot test fail as
+    /// `FailureKind::Unsupported`, which says nothing about the proxy.
+    pub async fn try_proxy(&self, protocol: &SupportedProtocols, proxy: &ProxyEndpoint) -> Result<ProxyTest, ProxyFailure> {
+        if !self.can_test(protocol) {
+            return Err(ProxyFailure::unsupported(proxy, protocol));
+        }
+
+        return self.test_proxy(protocol, proxy).await
+            .map_err(|e| ProxyFailure::new(proxy, Some(protocol), e.as_ref()));
+    }
+
+    /// Tests every protocol `proxy` might speak. Returns the working variants, or the failed
+    /// attempts if none worked.
+    pub async fn test_endpoint(&self, proxy: &ProxyEndpoint) -> Vec<Result<ProxyTest, ProxyFailure>> {
+        // An endpoint that was published with a scheme is only tested with that protocol family,
+        // anything else gets a full request only for the protocols its handshake answers to.
+        let protocols = match &proxy.scheme {
+            Some(scheme) => scheme.family(),
+            None => {
+                let direct = ProxyChain::new();
+                let upstream = self.upstream.as_ref().unwrap_or(&direct);
+                probe::detect_protocols_through(upstream, proxy, self.probe_timeout).await
+            },
+        };
+
+        if protocols.is_empty() {
+            return vec![Err(ProxyFailure::no_protocol(proxy))];
+        }
+
+        let (protocols, unsupported): (Vec<_>, Vec<_>) = protocols.into_iter().partition(|protocol| self.can_test(protocol));
+        if protocols.is_empty() {
+            return unsupported.iter().map(|protocol| ProxyFailure::unsupported(proxy, protocol)).map(Err).collect();
+        }
+
+        let mut alive = Vec::new();
+        let mut failures = Vec::new();
+        for protocol in protocols {
+            match self.try_proxy(&protocol, proxy).await {
+                Ok(test) => {
+                    #[cfg(feature = "logging")]
+                    log::debug!("success {} proxy {}", protocol, proxy);
+                    alive.push(Ok(test));
+                },
+                Err(failure) => {
+                    #[cfg(feature = "logging")]
+                    log::debug!("failed proxy {}: {} ({})", proxy, failure.kind, failure.message);
+                    failures.push(Err(failure));
+                }
+            }
+        }
+
+        // Failed variants of an otherwise working endpoint are not interesting.
+        return match alive.is_empty() {
+            true => failures,
+            false => alive,
+        };
+    }
+
+    /// Tests `proxies` on spawned tasks, at most `concurrency` endpoints at a time, yielding each
+    /// result as soon as it is known. A new endpoint starts as soon as any slot frees up, and
+    /// dropping the stream stops further tests from being started.
+    pub fn test_outcomes_stream<I>(&self, proxies: I) -> BoxStream<'static, Result<ProxyTest, ProxyFailure>>
+        where I: IntoIterator<Item = ProxyEndpoint> + Send + 'static, I::IntoIter: Send {
+        let (tx, rx) = mpsc::channel(self.concurrency.max(1));
+        let this = self.clone();
+        tokio::spawn(async move {
+            let tester = Arc::new(this);
+
+            let semaphore = Arc::new(Semaphore::new(tester.concurrency.max(1)));
+            for proxy in proxies {
+                let permit = match semaphore.clone().acquire_owned().await {
+                    Ok(permit) => permit,
+                    Err(_) => break
+                };
+
+                if tx.is_closed() {
+                    break;
+                }
+
+                let tx = tx.clone();
+                let tester = tester.clone();
+                tokio::spawn(async move {
+                    for result in tester.test_endpoint(&proxy).await {
+                        if tx.send(result).await.is_err() {
+                            break;
+                        }
+                    }
+                    drop(permit);
+                });
+            }
+        });
+
+        return stream::unfold(rx, |mut rx| async move {
+            return rx.recv().await.map(|result| (result, rx));
+        }).boxed();
+    }
+
+    /// Working proxies from `proxies`, as they are found.
+    pub fn test_proxies_stream<I>(&self, proxies: I) -> BoxStream<'static, ProxyTest>
+        where I: IntoIterator<Item = ProxyEndpoint> + Send + 'static, I::IntoIter: Send {
+        return self.test_outcomes_stream(proxies)
+            .filter_map(|result| async move { result.ok() })
+            .boxed();
+    }
+
+    pub async fn test_proxies(&self, proxies: &[ProxyEndpoint]) -> Result<ProxyTestOutcome, Box<dyn Error + Send + Sync>> {
+        #[cfg(feature = "logging")]
+        log::info!("testing {} proxies: {:?}", proxies.len(), proxies);
+
+        let mut outcome = ProxyTestOutcome::new();
+        let mut results = self.test_outcomes_stream(proxies.to_vec());
+        while let Some(result) = results.next().await {
+            match result {
+                Ok(test) => outcome.record_alive(test),
+                Err(failure) => outcome.record_dead(failure),
+            }
+        }
+
+        outcome.alive.sort();
+
+        #[cfg(feature = "logging")]
+        log::info!("{} working proxies, failures: {:?}", outcome.alive.len(), outcome.failure_counts);
+        return Ok(outcome);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use crate::outcome::FailureKind;
+    use crate::utility::BoxError;
+
+    #[tokio::test]
+    async fn test_https_behind_upstream_is_unsupported() -> Result<(), BoxError> {
+        let (first, first_targets) = crate::tunnel::spawn_connect_proxy().await?;
+        let tester = ProxyTester::new()
+            .without_judge()
+            .with_upstream(ProxyChain::new().hop(first, SupportedProtocols::Http));
+
+        let proxy = ProxyEndpoint::from_str("https://10.0.0.1:8443")?;
+        let failure = tester.try_proxy(&SupportedProtocols::Https, &proxy).await.err();
+        assert_eq!(failure.as_ref().map(|failure| failure.kind), Some(FailureKind::Unsupported));
+        assert_eq!(tester.test_endpoint(&proxy).await.len(), 1);
+        assert!(first_targets.lock().unwrap().is_empty());
+
+        let mut pool = crate::proxy::ProxyManager::new();
+        pool.record_failure(&failure.unwrap());
+        assert!(pool.health(&proxy).is_none());
+        return Ok(());
+    }
+
+    #[tokio::test]
+    async fn test_stream_reports_dead_endpoints() -> Result<(), BoxError> {
+        // Bind and drop listeners so the ports are known to refuse connections.
+        let mut proxies = Vec::new();
+        for _ in 0..5 {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+            proxies.push(ProxyEndpoint::from(listener.local_addr()?));
+        }
+
+        let tester = ProxyTester::new().without_judge().with_concurrency(2);
+        let outcome = tester.test_proxies(&proxies).await?;
+        assert!(outcome.alive.is_empty());
+        assert_eq!(outcome.dead.len(), proxies.len());
+        assert_eq!(outcome.failure_counts.get(&FailureKind::NoProtocol), Some(&proxies.len()));
+        return Ok(());
+    }
+}
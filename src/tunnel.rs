@@ -0,0 +1,453 @@
+use std::fmt;
+use std::convert::TryFrom;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use base64::Engine;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use crate::endpoint::ProxyEndpoint;
+use crate::proxy::SupportedProtocols;
+use crate::utility::BoxError;
+
+/// Longest HTTP head accepted from a proxy or a gateway client.
+pub const MAX_HEAD_LEN: usize = 16 * 1024;
+
+/// Destination of a tunnel. `host` is an IP address or a name, without brackets.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TargetAddr {
+    pub host: String,
+    pub port: u16,
+}
+
+impl TargetAddr {
+    pub fn new(host: &str, port: u16) -> TargetAddr {
+        let host = host.strip_prefix('[').and_then(|host| host.strip_suffix(']')).unwrap_or(host);
+        return TargetAddr {
+            host: host.to_string(),
+            port
+        }
+    }
+
+    pub fn ip(&self) -> Option<IpAddr> {
+        return IpAddr::from_str(&self.host).ok();
+    }
+
+    /// The target's address, looking the name up locally if necessary.
+    pub async fn resolve(&self) -> Result<SocketAddr, BoxError> {
+        if let Some(ip) = self.ip() {
+            return Ok(SocketAddr::new(ip, self.port));
+        }
+
+        return tokio::net::lookup_host((self.host.as_str(), self.port)).await?
+            .next()
+            .ok_or_else(|| format!("no address for {}", self.host).into());
+    }
+}
+
+impl fmt::Display for TargetAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match Ipv6Addr::from_str(&self.host) {
+            Ok(ip) => write!(f, "[{}]:{}", ip, self.port),
+            Err(_) => write!(f, "{}:{}", self.host, self.port),
+        }
+    }
+}
+
+impl FromStr for TargetAddr {
+    type Err = BoxError;
+
+    /// Parses `host:port` and `[v6]:port`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (host, port) = s.rsplit_once(':').ok_or_else(|| format!("missing port: {}", s))?;
+        if host.is_empty() {
+            return Err(format!("missing host: {}", s).into());
+        }
+
+        return Ok(TargetAddr::new(host, port.parse()?));
+    }
+}
+
+impl From<SocketAddr> for TargetAddr {
+    fn from(addr: SocketAddr) -> Self {
+        return TargetAddr::new(&addr.ip().to_string(), addr.port());
+    }
+}
+
+/// Reads up to and including the blank line that ends an HTTP head, one byte at a time so
+/// that nothing after it is consumed.
+pub async fn read_http_head<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Vec<u8>, BoxError> {
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HEAD_LEN {
+            return Err(obfstr::obfstr!("http head too long").into());
+        }
+
+        stream.read_exact(&mut byte).await?;
+        head.push(byte[0]);
+    }
+
+    return Ok(head);
+}
+
+/// Connects to `proxy` and has it open a tunnel to `target` with `protocol`'s handshake.
+/// The returned stream carries the tunnelled connection.
+pub async fn connect(proxy: &ProxyEndpoint, protocol: &SupportedProtocols, target: &TargetAddr) -> Result<TcpStream, BoxError> {
+    let mut stream = TcpStream::connect(proxy.authority()).await?;
+    handshake(&mut stream, proxy, protocol, target).await?;
+    return Ok(stream);
+}
+
+/// Has the proxy at the other end of `stream` open a tunnel to `target`. The stream may itself
+/// be a tunnel, which is how chains nest.
+pub async fn handshake<S>(stream: &mut S, proxy: &ProxyEndpoint, protocol: &SupportedProtocols, target: &TargetAddr) -> Result<(), BoxError>
+    where S: AsyncRead + AsyncWrite + Unpin {
+    return match protocol {
+        SupportedProtocols::Http => http_connect(stream, proxy, target).await,
+        SupportedProtocols::Socks4 => socks4_connect(stream, proxy, target, false).await,
+        SupportedProtocols::Socks4a => socks4_connect(stream, proxy, target, true).await,
+        SupportedProtocols::Socks5 => socks5_connect(stream, proxy, target, false).await,
+        SupportedProtocols::Socks5h => socks5_connect(stream, proxy, target, true).await,
+        SupportedProtocols::Https => Err(obfstr::obfstr!("tunnels through https proxies are not supported").into()),
+    };
+}
+
+pub async fn http_connect<S>(stream: &mut S, proxy: &ProxyEndpoint, target: &TargetAddr) -> Result<(), BoxError>
+    where S: AsyncRead + AsyncWrite + Unpin {
+    let mut request = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n", target = target);
+    if let Some(credentials) = &proxy.credentials {
+        let token = base64::engine::general_purpose::STANDARD.encode(credentials.to_string());
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    let head = read_http_head(stream).await?;
+    let mut headers = [httparse::EMPTY_HEADER; 64];
+    let mut response = httparse::Response::new(&mut headers);
+    response.parse(&head)?;
+
+    return match response.code {
+        Some(code) if (200..300).contains(&code) => Ok(()),
+        Some(407) => Err(obfstr::obfstr!("proxy authentication required").into()),
+        code => Err(format!("proxy refused CONNECT to {}: {:?}", target, code).into()),
+    };
+}
+
+/// SOCKS4 can only address IPv4 targets itself; SOCKS4a passes names on to the proxy.
+pub async fn socks4_connect<S>(stream: &mut S, proxy: &ProxyEndpoint, target: &TargetAddr, remote_dns: bool) -> Result<(), BoxError>
+    where S: AsyncRead + AsyncWrite + Unpin {
+    let mut request = vec![0x04, 0x01];
+    request.extend_from_slice(&target.port.to_be_bytes());
+
+    let domain = match (target.ip(), remote_dns) {
+        (Some(IpAddr::V4(ip)), _) => {
+            request.extend_from_slice(&ip.octets());
+            None
+        },
+        (None, true) => {
+            // 0.0.0.x tells a SOCKS4a proxy that the name follows the user id.
+            request.extend_from_slice(&[0, 0, 0, 1]);
+            Some(target.host.as_str())
+        },
+        _ => match target.resolve().await? {
+            SocketAddr::V4(addr) => {
+                request.extend_from_slice(&addr.ip().octets());
+                None
+            },
+            SocketAddr::V6(_) => return Err(format!("socks4 cannot reach ipv6 target {}", target).into()),
+        },
+    };
+
+    if let Some(credentials) = &proxy.credentials {
+        request.extend_from_slice(credentials.username.as_bytes());
+    }
+    request.push(0x00);
+
+    if let Some(domain) = domain {
+        request.extend_from_slice(domain.as_bytes());
+        request.push(0x00);
+    }
+
+    stream.write_all(&request).await?;
+    let mut reply = [0u8; 8];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0x5a {
+        return Err(format!("socks4 proxy refused {}: {:#04x}", target, reply[1]).into());
+    }
+
+    return Ok(());
+}
+
+/// The length prefix of a SOCKS5 field, which cannot describe more than 255 bytes.
+fn socks5_field_len(field: &str, name: &str) -> Result<u8, BoxError> {
+    return u8::try_from(field.len())
+        .map_err(|_| format!("socks5 {} is {} bytes long, at most 255 fit", name, field.len()).into());
+}
+
+pub async fn socks5_connect<S>(stream: &mut S, proxy: &ProxyEndpoint, target: &TargetAddr, remote_dns: bool) -> Result<(), BoxError>
+    where S: AsyncRead + AsyncWrite + Unpin {
+    let greeting: &[u8] = match proxy.credentials {
+        Some(_) => &[0x05, 0x02, 0x00, 0x02],
+        None => &[0x05, 0x01, 0x00],
+    };
+    stream.write_all(greeting).await?;
+
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await?;
+    match (choice[1], &proxy.credentials) {
+        (0x00, _) => {},
+        (0x02, Some(credentials)) => {
+            let mut auth = vec![0x01, socks5_field_len(&credentials.username, "username")?];
+            auth.extend_from_slice(credentials.username.as_bytes());
+            auth.push(socks5_field_len(&credentials.password, "password")?);
+            auth.extend_from_slice(credentials.password.as_bytes());
+            stream.write_all(&auth).await?;
+
+            let mut status = [0u8; 2];
+            stream.read_exact(&mut status).await?;
+            if status[1] != 0x00 {
+                return Err(obfstr::obfstr!("socks5 proxy rejected our credentials, auth failed").into());
+            }
+        },
+        _ => return Err(obfstr::obfstr!("socks5 proxy requires an auth method we do not offer").into()),
+    }
+
+    let mut request = vec![0x05, 0x01, 0x00];
+    let ip = match (target.ip(), remote_dns) {
+        (Some(ip), _) => Some(ip),
+        (None, true) => None,
+        (None, false) => Some(target.resolve().await?.ip()),
+    };
+
+    match ip {
+        Some(IpAddr::V4(ip)) => {
+            request.push(0x01);
+            request.extend_from_slice(&ip.octets());
+        },
+        Some(IpAddr::V6(ip)) => {
+            request.push(0x04);
+            request.extend_from_slice(&ip.octets());
+        },
+        None => {
+            request.push(0x03);
+            request.push(socks5_field_len(&target.host, "hostname")?);
+            request.extend_from_slice(target.host.as_bytes());
+        },
+    }
+    request.extend_from_slice(&target.port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0x00 {
+        return Err(format!("socks5 proxy refused {}: {:#04x}", target, reply[1]).into());
+    }
+
+    // Skip the bound address, which we have no use for.
+    let bound_len = match reply[3] {
+        0x01 => 4,
+        0x04 => 16,
+        0x03 => stream.read_u8().await? as usize,
+        atyp => return Err(format!("socks5 proxy sent unknown address type {:#04x}", atyp).into()),
+    };
+    let mut bound = vec![0u8; bound_len + 2];
+    stream.read_exact(&mut bound).await?;
+    return Ok(());
+}
+
+/// Decides whether a SOCKS5 client's username and password let it in.
+pub(crate) type Socks5Authenticator<'a> = &'a (dyn Fn(&str, &str) -> bool + Sync);
+
+/// Server side of a SOCKS5 CONNECT handshake, up to but excluding the reply. With
+/// `authenticate`, clients must log in with a username and password it accepts, and the
+/// username is returned; otherwise only no-auth is accepted. Requests we cannot serve are
+/// answered with an error reply.
+pub(crate) async fn accept_socks5<S>(client: &mut S, authenticate: Option<Socks5Authenticator<'_>>) -> Result<(TargetAddr, Option<String>), BoxError>
+    where S: AsyncRead + AsyncWrite + Unpin {
+    let mut greeting = [0u8; 2];
+    client.read_exact(&mut greeting).await?;
+    if greeting[0] != 0x05 {
+        return Err(format!("not a socks5 greeting: version {:#04x}", greeting[0]).into());
+    }
+
+    let mut methods = vec![0u8; greeting[1] as usize];
+    client.read_exact(&mut methods).await?;
+
+    let username = match authenticate {
+        Some(authenticate) => {
+            if !methods.contains(&0x02) {
+                client.write_all(&[0x05, 0xff]).await?;
+                return Err(obfstr::obfstr!("socks5 client does not offer username/password").into());
+            }
+
+            client.write_all(&[0x05, 0x02]).await?;
+            let mut version_and_len = [0u8; 2];
+            client.read_exact(&mut version_and_len).await?;
+            if version_and_len[0] != 0x01 {
+                return Err(format!("unknown socks5 auth version {:#04x}", version_and_len[0]).into());
+            }
+
+            let mut name = vec![0u8; version_and_len[1] as usize];
+            client.read_exact(&mut name).await?;
+            let mut password = vec![0u8; client.read_u8().await? as usize];
+            client.read_exact(&mut password).await?;
+
+            let name = String::from_utf8(name)?;
+            if !authenticate(&name, &String::from_utf8_lossy(&password)) {
+                client.write_all(&[0x01, 0x01]).await?;
+                return Err(obfstr::obfstr!("socks5 client credentials rejected").into());
+            }
+
+            client.write_all(&[0x01, 0x00]).await?;
+            Some(name)
+        },
+        None if methods.contains(&0x00) => {
+            client.write_all(&[0x05, 0x00]).await?;
+            None
+        },
+        None => {
+            client.write_all(&[0x05, 0xff]).await?;
+            return Err(obfstr::obfstr!("socks5 client does not offer no-auth").into());
+        },
+    };
+
+    let mut request = [0u8; 4];
+    client.read_exact(&mut request).await?;
+    if request[1] != 0x01 {
+        client.write_all(&socks5_reply(0x07)).await?;
+        return Err(format!("unsupported socks5 command {:#04x}", request[1]).into());
+    }
+
+    let host = match request[3] {
+        0x01 => {
+            let mut ip = [0u8; 4];
+            client.read_exact(&mut ip).await?;
+            IpAddr::from(ip).to_string()
+        },
+        0x04 => {
+            let mut ip = [0u8; 16];
+            client.read_exact(&mut ip).await?;
+            IpAddr::from(ip).to_string()
+        },
+        0x03 => {
+            let mut name = vec![0u8; client.read_u8().await? as usize];
+            client.read_exact(&mut name).await?;
+            String::from_utf8(name)?
+        },
+        atyp => {
+            client.write_all(&socks5_reply(0x08)).await?;
+            return Err(format!("unsupported socks5 address type {:#04x}", atyp).into());
+        }
+    };
+
+    return Ok((TargetAddr::new(&host, client.read_u16().await?), username));
+}
+
+/// A SOCKS5 reply with `status` and an unspecified bound address.
+pub(crate) fn socks5_reply(status: u8) -> [u8; 10] {
+    return [0x05, status, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+}
+
+/// A plain HTTP server that answers every request with `body`.
+#[cfg(test)]
+pub(crate) async fn spawn_http_server(body: &'static str) -> Result<SocketAddr, BoxError> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                if read_http_head(&mut stream).await.is_ok() {
+                    let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+                    let _ = stream.write_all(response.as_bytes()).await;
+                }
+            });
+        }
+    });
+
+    return Ok(addr);
+}
+
+/// An HTTP proxy that only understands CONNECT, recording the targets it was asked for.
+#[cfg(test)]
+pub(crate) async fn spawn_connect_proxy() -> Result<(ProxyEndpoint, std::sync::Arc<std::sync::Mutex<Vec<String>>>), BoxError> {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let proxy = ProxyEndpoint::from(listener.local_addr()?);
+    let targets = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let seen = targets.clone();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let seen = seen.clone();
+            tokio::spawn(async move {
+                let head = String::from_utf8(read_http_head(&mut stream).await?)?;
+                let target = head.split_whitespace().nth(1).unwrap_or_default().to_string();
+                seen.lock().unwrap().push(target.clone());
+
+                let mut upstream = TcpStream::connect(target).await?;
+                stream.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n").await?;
+                tokio::io::copy_bidirectional(&mut stream, &mut upstream).await?;
+                return Ok::<(), BoxError>(());
+            });
+        }
+    });
+
+    return Ok((proxy, targets));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // SOCKS4 CONNECT to 1.1.1.1:80, as a client that does not speak SOCKS5 would send it.
+    const SOCKS4_GREETING: [u8; 9] = [0x04, 0x01, 0x00, 0x50, 0x01, 0x01, 0x01, 0x01, 0x00];
+
+    #[test]
+    fn test_target_addr() -> Result<(), BoxError> {
+        assert_eq!(TargetAddr::from_str("example.com:443")?, TargetAddr::new("example.com", 443));
+        assert_eq!(TargetAddr::from_str("[2001:db8::1]:80")?.host, "2001:db8::1");
+        assert_eq!(TargetAddr::new("2001:db8::1", 80).to_string(), "[2001:db8::1]:80");
+        assert!(TargetAddr::from_str("example.com").is_err());
+        return Ok(());
+    }
+
+    #[tokio::test]
+    async fn test_accept_socks5_checks_version_and_credentials() -> Result<(), BoxError> {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(&SOCKS4_GREETING).await?;
+        assert!(accept_socks5(&mut server, None).await.is_err());
+
+        // No-auth clients are turned away when credentials are required.
+        let accept = |username: &str, password: &str| username == "route" && password == "secret";
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(&[0x05, 0x01, 0x00]).await?;
+        assert!(accept_socks5(&mut server, Some(&accept)).await.is_err());
+        let mut choice = [0u8; 2];
+        client.read_exact(&mut choice).await?;
+        assert_eq!(choice, [0x05, 0xff]);
+
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        client.write_all(&[0x05, 0x01, 0x02, 0x01, 0x05]).await?;
+        client.write_all(b"route\x05guess").await?;
+        assert!(accept_socks5(&mut server, Some(&accept)).await.is_err());
+        return Ok(());
+    }
+
+    #[tokio::test]
+    async fn test_socks5_rejects_oversized_fields() -> Result<(), BoxError> {
+        let proxy = ProxyEndpoint::from_str("127.0.0.1:1080")?;
+        let target = TargetAddr::new(&format!("{}.example.com", "a".repeat(250)), 443);
+
+        // The proxy's no-auth choice is already waiting when the request is built.
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        server.write_all(&[0x05, 0x00]).await?;
+        let error = socks5_connect(&mut client, &proxy, &target, true).await.unwrap_err();
+        assert!(error.to_string().contains("hostname is 262 bytes long"));
+
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        server.write_all(&[0x05, 0x02]).await?;
+        let proxy = proxy.with_credentials(&"u".repeat(256), "pass");
+        let error = socks5_connect(&mut client, &proxy, &TargetAddr::new("example.com", 443), true).await.unwrap_err();
+        assert!(error.to_string().contains("username is 256 bytes long"));
+        return Ok(());
+    }
+}
@@ -1,4 +1,4 @@
-use rand::seq::{SliceRandom, IteratorRandom};
+use rand::seq::SliceRandom;
 
 pub fn random_user_agent() -> &'static str {
     lazy_static::lazy_static! {
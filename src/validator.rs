@@ -0,0 +1,222 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use regex::Regex;
+use reqwest::{StatusCode, Url};
+use sha2::{Digest, Sha256};
+use crate::random_user_agent;
+use crate::utility::BoxError;
+
+/// What a validator saw when its request went through the proxy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationResponse {
+    pub status: StatusCode,
+    pub text: String,
+    pub rtt: Duration,
+}
+
+/// Reasons a response that did arrive is still not accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    UnexpectedStatus(StatusCode),
+    BodyMismatch,
+    HashMismatch(String),
+    TooSlow(Duration),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::UnexpectedStatus(status) => write!(f, "unexpected status {}", status),
+            ValidationError::BodyMismatch => write!(f, "body does not match"),
+            ValidationError::HashMismatch(hash) => write!(f, "body hash {} does not match", hash),
+            ValidationError::TooSlow(rtt) => write!(f, "response took {:?}", rtt),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Decides whether a proxy works by sending a request through a client configured with it.
+#[async_trait::async_trait]
+pub trait ProxyValidator: fmt::Debug + Send + Sync {
+    async fn validate(&self, client: &reqwest::Client) -> Result<ValidationResponse, BoxError>;
+}
+
+/// Requests `target` and checks the status, body and latency of the response.
+#[derive(Debug, Clone)]
+pub struct ResponseValidator {
+    pub target: Url,
+    pub timeout: Duration,
+    /// Accepted status codes, any 2xx when empty.
+    pub expected_status: Vec<StatusCode>,
+    pub body_pattern: Option<Regex>,
+    /// Lowercase hex SHA-256 of the expected body.
+    pub body_sha256: Option<String>,
+    pub max_latency: Option<Duration>,
+}
+
+impl ResponseValidator {
+    pub fn new(target: Url) -> ResponseValidator {
+        return ResponseValidator {
+            target,
+            timeout: Duration::from_secs(30),
+            expected_status: Vec::new(),
+            body_pattern: None,
+            body_sha256: None,
+            max_latency: None
+        }
+    }
+
+    pub fn expect_status(mut self, status: StatusCode) -> ResponseValidator {
+        self.expected_status.push(status);
+        return self;
+    }
+
+    pub fn expect_body(mut self, pattern: Regex) -> ResponseValidator {
+        self.body_pattern = Some(pattern);
+        return self;
+    }
+
+    pub fn expect_sha256(mut self, hash: &str) -> ResponseValidator {
+        self.body_sha256 = Some(hash.to_ascii_lowercase());
+        return self;
+    }
+
+    pub fn max_latency(mut self, latency: Duration) -> ResponseValidator {
+        self.max_latency = Some(latency);
+        return self;
+    }
+
+    pub fn check(&self, response: &ValidationResponse) -> Result<(), ValidationError> {
+        let status_ok = match self.expected_status.is_empty() {
+            true => response.status.is_success(),
+            false => self.expected_status.contains(&response.status),
+        };
+
+        if !status_ok {
+            return Err(ValidationError::UnexpectedStatus(response.status));
+        }
+
+        if let Some(pattern) = &self.body_pattern {
+            if !pattern.is_match(&response.text) {
+                return Err(ValidationError::BodyMismatch);
+            }
+        }
+
+        if let Some(expected) = &self.body_sha256 {
+            let hash = sha256_hex(response.text.as_bytes());
+            if &hash != expected {
+                return Err(ValidationError::HashMismatch(hash));
+            }
+        }
+
+        if let Some(max_latency) = self.max_latency {
+            if response.rtt > max_latency {
+                return Err(ValidationError::TooSlow(response.rtt));
+            }
+        }
+
+        return Ok(());
+    }
+
+    async fn fetch(&self, client: &reqwest::Client) -> Result<ValidationResponse, BoxError> {
+        let before_get = Instant::now();
+        let response = client.get(self.target.clone())
+            .header(obfstr::obfstr!("User-Agent"), random_user_agent())
+            .header(obfstr::obfstr!("Content-Type"), obfstr::obfstr!("application/x-www-form-urlencoded"))
+            .header(obfstr::obfstr!("Accept-Language"), obfstr::obfstr!("en-US,en;q=0.9"))
+            .timeout(self.timeout)
+            .send().await?;
+
+        let status = response.status();
+        let text = response.text().await?;
+        let rtt = Instant::now().duration_since(before_get);
+        return Ok(ValidationResponse { status, text, rtt });
+    }
+}
+
+#[async_trait::async_trait]
+impl ProxyValidator for ResponseValidator {
+    async fn validate(&self, client: &reqwest::Client) -> Result<ValidationResponse, BoxError> {
+        let response = self.fetch(client).await?;
+        self.check(&response)?;
+        return Ok(response);
+    }
+}
+
+/// The default validator: an IP echo service must answer 200 with a body that parses as an
+/// address, which rejects captive portals and error pages that a bare request would accept.
+#[derive(Debug, Clone)]
+pub struct IpEchoValidator {
+    pub inner: ResponseValidator,
+}
+
+impl Default for IpEchoValidator {
+    fn default() -> Self {
+        let target = Url::from_str(obfstr::obfstr!("https://api.ipify.org/"))
+            .expect(obfstr::obfstr!("default validator url"));
+        return IpEchoValidator::new(target);
+    }
+}
+
+impl IpEchoValidator {
+    pub fn new(target: Url) -> IpEchoValidator {
+        return IpEchoValidator {
+            inner: ResponseValidator::new(target).expect_status(StatusCode::OK)
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ProxyValidator for IpEchoValidator {
+    async fn validate(&self, client: &reqwest::Client) -> Result<ValidationResponse, BoxError> {
+        let response = self.inner.validate(client).await?;
+        if IpAddr::from_str(response.text.trim()).is_err() {
+            return Err(ValidationError::BodyMismatch.into());
+        }
+
+        return Ok(response);
+    }
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    return Sha256::digest(bytes).iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_response_checks() -> Result<(), BoxError> {
+        let response = ValidationResponse {
+            status: StatusCode::OK,
+            text: "203.0.113.7".to_string(),
+            rtt: Duration::from_millis(300)
+        };
+
+        let validator = ResponseValidator::new(Url::from_str("http://127.0.0.1/")?);
+        assert_eq!(validator.check(&response), Ok(()));
+        assert_eq!(
+            validator.clone().expect_status(StatusCode::NO_CONTENT).check(&response),
+            Err(ValidationError::UnexpectedStatus(StatusCode::OK))
+        );
+        assert_eq!(
+            validator.clone().expect_body(Regex::new("^[a-z]+$")?).check(&response),
+            Err(ValidationError::BodyMismatch)
+        );
+        assert_eq!(
+            validator.clone().expect_sha256(&sha256_hex(b"203.0.113.7")).check(&response),
+            Ok(())
+        );
+        assert_eq!(
+            validator.max_latency(Duration::from_millis(100)).check(&response),
+            Err(ValidationError::TooSlow(Duration::from_millis(300)))
+        );
+        return Ok(());
+    }
+}